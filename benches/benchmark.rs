@@ -1,6 +1,8 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
-use pixlib::codec::rcr;
+use pixlib::codec::{ppm, rcr};
+use pixlib::color::Rgb8;
+use pixlib::image::Image;
 
 fn rcr_benchmark(c: &mut Criterion) {
     let s: [u8; 64] = [
@@ -28,21 +30,36 @@ fn rcr_benchmark(c: &mut Criterion) {
         u.dct();
     }));
     
+    c.bench_function("fast dct f", |b| b.iter(|| {
+        let u = black_box(u_f32);
+        u.fast_dct();
+    }));
+
+    c.bench_function("fast inverse dct f", |b| b.iter(|| {
+        let u = black_box(u_f32);
+        u.fast_inv_dct();
+    }));
+
+    c.bench_function("fast dct i", |b| b.iter(|| {
+        let u = black_box(u_i32);
+        u.fast_dct();
+    }));
+    
     c.bench_function("quantize", |b| b.iter(|| {
         let u = black_box(u_i32);
-        let (t, _) = rcr::tables::from_quality(5);
+        let (t, _) = rcr::tables::scaled(75).unwrap();
         u.quantize(t);
     }));
 
     c.bench_function("inverse quantize", |b| b.iter(|| {
         let u = black_box(u_i32);
-        let (t, _) = rcr::tables::from_quality(5);
+        let (t, _) = rcr::tables::scaled(75).unwrap();
         u.inv_quantize(t);
     }));
 
     c.bench_function("full encode f32", |b| b.iter(|| {
         let u = black_box(u_u8);
-        let (t, _) = rcr::tables::from_quality(5);
+        let (t, _) = rcr::tables::scaled(75).unwrap();
         u.convert(|x| x as f32)
             .dct()
             .convert(|x| x as i32)
@@ -54,7 +71,7 @@ fn rcr_benchmark(c: &mut Criterion) {
 
     c.bench_function("full encode i32", |b| b.iter(|| {
         let u = black_box(u_u8);
-        let (t, _) = rcr::tables::from_quality(5);
+        let (t, _) = rcr::tables::scaled(75).unwrap();
         u.convert(|x| x as i32)
             .dct()
             .quantize(t)
@@ -64,5 +81,75 @@ fn rcr_benchmark(c: &mut Criterion) {
     }));
 }
 
-criterion_group!(benches, rcr_benchmark);
+/// Sample image tiled 4x4 times, large enough to keep several threads busy.
+fn large_image() -> Image<Rgb8> {
+    let tile = ppm::decode(&include_bytes!("../examples/in_192x192.ppm")[..]).unwrap();
+    let (w, h) = (tile.width(), tile.height());
+
+    let data = (0..16 * w * h)
+        .map(|i| (i % (4 * w), i / (4 * w)))
+        .map(|(x, y)| *tile.at(x % w, y % h))
+        .collect();
+
+    Image::new(4 * w, 4 * h, data)
+}
+
+/// Whole image coding, run with `--features parallel` to compare against the
+/// serial path.
+fn codec_benchmark(c: &mut Criterion) {
+    let image = large_image();
+    let settings = rcr::Settings::quality(75).unwrap();
+
+    let mut data = Vec::new();
+    rcr::encode(&mut data, settings, &image).unwrap();
+
+    c.bench_function("rcr encode 768x768", |b| b.iter(|| {
+        let mut out = Vec::new();
+        rcr::encode(&mut out, settings, black_box(&image)).unwrap();
+        out
+    }));
+
+    c.bench_function("rcr decode 768x768", |b| b.iter(|| {
+        rcr::decode::<_, Rgb8>(black_box(&data[..])).unwrap()
+    }));
+}
+
+/// Scaling of whole image coding with the number of threads.
+#[cfg(feature = "parallel")]
+fn thread_benchmark(c: &mut Criterion) {
+    use criterion::BenchmarkId;
+
+    let image = large_image();
+    let settings = rcr::Settings::quality(75).unwrap();
+
+    let mut data = Vec::new();
+    rcr::encode(&mut data, settings, &image).unwrap();
+
+    let mut group = c.benchmark_group("rcr threads");
+    let max = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let counts = std::iter::successors(Some(1), |&n| Some(n * 2)).take_while(|&n| n <= max);
+
+    for threads in counts {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+
+        group.bench_with_input(BenchmarkId::new("encode", threads), &threads, |b, _| b.iter(|| {
+            pool.install(|| {
+                let mut out = Vec::new();
+                rcr::encode(&mut out, settings, black_box(&image)).unwrap();
+                out
+            })
+        }));
+
+        group.bench_with_input(BenchmarkId::new("decode", threads), &threads, |b, _| b.iter(|| {
+            pool.install(|| rcr::decode::<_, Rgb8>(black_box(&data[..])).unwrap())
+        }));
+    }
+
+    group.finish();
+}
+
+#[cfg(not(feature = "parallel"))]
+criterion_group!(benches, rcr_benchmark, codec_benchmark);
+#[cfg(feature = "parallel")]
+criterion_group!(benches, rcr_benchmark, codec_benchmark, thread_benchmark);
 criterion_main!(benches);
\ No newline at end of file
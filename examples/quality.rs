@@ -1,25 +1,35 @@
 use std::fs;
-use std::io::{Cursor, Result};
+use std::io::Cursor;
 use pixlib::codec::*;
-use pixlib::color::Lab8;
+use pixlib::color::Rgb8;
 use pixlib::image::Image;
+use pixlib::metrics;
+use pixlib::Result;
 
 fn main() -> Result<()> {
     let file = fs::read("./examples/in_192x192.ppm")?;
-    let img: Image<Lab8> = ppm::decode(Cursor::new(file)).into();
+    let original = ppm::decode(Cursor::new(file))?;
     
-    for q in 0..8 {
+    for q in [5, 10, 25, 50, 75, 90, 95, 100] {
         let mut rcr_data = Vec::new();
-        rcr::encode(Cursor::new(&mut rcr_data), rcr::Settings::quality(q), &img)?;
+        rcr::encode(Cursor::new(&mut rcr_data), rcr::Settings::quality(q)?, &original)?;
         
-        let img = rcr::decode(Cursor::new(&rcr_data))?;
+        let img: Image<Rgb8> = rcr::decode(Cursor::new(&rcr_data))?;
+
+        println!(
+            "q{:<3} {:>6} bytes  PSNR {:>6.2} dB  SSIM {:.4}",
+            q,
+            rcr_data.len(),
+            metrics::psnr(&original, &img)?.combined,
+            metrics::ssim(&original, &img)?.combined,
+        );
         
         let mut ppm_data = Vec::new();
-        ppm::encode(Cursor::new(&mut ppm_data), &img.into());
+        ppm::encode(Cursor::new(&mut ppm_data), &img)?;
         
         fs::write(format!("./examples/tmp/out_q{}.ppm", q), ppm_data)?;
         fs::write(format!("./examples/tmp/out_q{}.rcr", q), rcr_data)?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
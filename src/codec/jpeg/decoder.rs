@@ -0,0 +1,554 @@
+use std::io::Read;
+
+use super::*;
+use crate::codec::rcr::entropy::{BitReader, HuffmanTable, decode_block};
+use crate::codec::rcr::header::{ColorModel, Precision};
+use crate::codec::rcr::sampling::{self, Upsampling};
+use crate::codec::rcr::transform;
+use crate::codec::rcr::unit::Unit;
+use crate::color::Rgb8;
+use crate::error::{Error, Result};
+use crate::image::Image;
+
+/// Color component of a frame.
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    /// Quantization table slot, looked up when a scan starts
+    table: usize,
+    /// Width of the plane, covering whole MCUs
+    stride: usize,
+    /// Decoded samples centered around zero, growing by rows of blocks as
+    /// they are decoded
+    samples: Vec<i32>,
+}
+
+impl Component {
+    /// Stores the samples of the block at block coordinates `(x, y)`.
+    fn store(&mut self, x: usize, y: usize, samples: Unit<i32>) {
+        let stride = self.stride;
+        let end = (8 * y + 8) * stride;
+        if self.samples.len() < end {
+            self.samples.resize(end, 0);
+        }
+
+        for (i, sample) in samples.into_iter().enumerate() {
+            self.samples[(8 * y + i / 8) * stride + 8 * x + i % 8] = sample.clamp(-128, 127);
+        }
+    }
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    h_max: usize,
+    v_max: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+}
+
+impl Frame {
+    fn read(segment: &[u8]) -> Result<Self> {
+        if segment.len() < 6 {
+            return Err(Error::InvalidHeader);
+        }
+
+        if segment[0] != 8 {
+            return Err(Error::UnsupportedFeature("JPEG sample precision"));
+        }
+
+        let height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+        let width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidDimensions { width, height });
+        }
+
+        let count = segment[5] as usize;
+        if count != 1 && count != 3 {
+            return Err(Error::UnsupportedFeature("JPEG component count"));
+        }
+        if segment.len() != 6 + 3 * count {
+            return Err(Error::InvalidHeader);
+        }
+
+        let mut components: Vec<Component> = segment[6..]
+            .chunks(3)
+            .map(|c| {
+                let (h, v) = ((c[1] >> 4) as usize, (c[1] & 0x0f) as usize);
+                match (1..=4).contains(&h) && (1..=4).contains(&v) && c[2] < 4 {
+                    true => Ok(Component {
+                        id: c[0],
+                        h,
+                        v,
+                        table: c[2] as usize,
+                        stride: 0,
+                        samples: Vec::new(),
+                    }),
+                    false => Err(Error::InvalidHeader),
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        let h_max = components.iter().map(|c| c.h).max().unwrap();
+        let v_max = components.iter().map(|c| c.v).max().unwrap();
+
+        // Chroma is upsampled by whole factors
+        if components.iter().any(|c| h_max % c.h != 0 || v_max % c.v != 0) {
+            return Err(Error::UnsupportedFeature("JPEG sampling factors"));
+        }
+
+        let mcus_x = width.div_ceil(8 * h_max);
+        let mcus_y = height.div_ceil(8 * v_max);
+        for c in &mut components {
+            c.stride = 8 * mcus_x * c.h;
+        }
+
+        Ok(Self { width, height, components, h_max, v_max, mcus_x, mcus_y })
+    }
+
+    /// Number of samples of component `c` in each direction.
+    fn size(&self, c: usize) -> (usize, usize) {
+        let component = &self.components[c];
+        (
+            (self.width * component.h).div_ceil(self.h_max),
+            (self.height * component.v).div_ceil(self.v_max),
+        )
+    }
+}
+
+/// Tables and frame defined by the segments read so far.
+#[derive(Default)]
+struct Decoder {
+    quantization: [Option<Unit<i32>>; 4],
+    dc: [Option<HuffmanTable>; 4],
+    ac: [Option<HuffmanTable>; 4],
+    /// MCUs between restart markers, zero if there are none
+    restart_interval: usize,
+    frame: Option<Frame>,
+    scans: usize,
+}
+
+impl Decoder {
+    fn read_quantization(&mut self, mut segment: &[u8]) -> Result<()> {
+        while let Some((&info, rest)) = segment.split_first() {
+            let (precision, slot) = ((info >> 4) as usize, (info & 0x0f) as usize);
+            if precision > 1 || slot > 3 || rest.len() < 64 * (precision + 1) {
+                return Err(Error::InvalidHeader);
+            }
+
+            // Entries are kept in zigzag order, like the coefficients
+            let entry = |i: usize| match precision {
+                0 => rest[i] as i32,
+                _ => u16::from_be_bytes([rest[2 * i], rest[2 * i + 1]]) as i32,
+            };
+            self.quantization[slot] = Some(Unit::new(std::array::from_fn(entry)));
+
+            segment = &rest[64 * (precision + 1)..];
+        }
+
+        Ok(())
+    }
+
+    fn read_huffman(&mut self, mut segment: &[u8]) -> Result<()> {
+        while segment.len() >= 17 {
+            let (class, slot) = (segment[0] >> 4, (segment[0] & 0x0f) as usize);
+            if class > 1 || slot > 3 {
+                return Err(Error::InvalidHeader);
+            }
+
+            let mut counts = [0; 16];
+            counts.copy_from_slice(&segment[1..17]);
+            let total: usize = counts.iter().map(|&n| n as usize).sum();
+            let symbols = segment.get(17..17 + total).ok_or(Error::InvalidHeader)?;
+
+            let table = Some(HuffmanTable::new(counts, symbols.to_vec())?);
+            match class {
+                0 => self.dc[slot] = table,
+                _ => self.ac[slot] = table,
+            }
+
+            segment = &segment[17 + total..];
+        }
+
+        match segment.is_empty() {
+            true => Ok(()),
+            false => Err(Error::InvalidHeader),
+        }
+    }
+
+    fn read_restart_interval(&mut self, segment: &[u8]) -> Result<()> {
+        match segment {
+            &[a, b] => self.restart_interval = u16::from_be_bytes([a, b]) as usize,
+            _ => return Err(Error::InvalidHeader),
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the scan described by `segment` from the entropy coded `data`
+    /// following it, returning the number of bytes consumed.
+    fn read_scan(&mut self, segment: &[u8], data: &[u8]) -> Result<usize> {
+        let frame = self.frame.as_mut().ok_or(Error::InvalidHeader)?;
+
+        let count = *segment.first().ok_or(Error::InvalidHeader)? as usize;
+        if !(1..=4).contains(&count) || segment.len() != 4 + 2 * count {
+            return Err(Error::InvalidHeader);
+        }
+
+        // Baseline scans cover every coefficient in a single pass
+        if segment[1 + 2 * count..] != [0, 63, 0] {
+            return Err(Error::UnsupportedFeature("JPEG spectral selection"));
+        }
+
+        let mut scan = Vec::with_capacity(count);
+        for selector in segment[1..1 + 2 * count].chunks(2) {
+            let c = frame.components
+                .iter()
+                .position(|c| c.id == selector[0])
+                .ok_or(Error::InvalidHeader)?;
+            let (dc, ac) = ((selector[1] >> 4) as usize, (selector[1] & 0x0f) as usize);
+
+            let dc = self.dc.get(dc).cloned().flatten().ok_or(Error::InvalidData)?;
+            let ac = self.ac.get(ac).cloned().flatten().ok_or(Error::InvalidData)?;
+            let table = self.quantization[frame.components[c].table].ok_or(Error::InvalidData)?;
+
+            // A single component is coded block by block instead of in MCUs
+            let factors = match count {
+                1 => (1, 1),
+                _ => (frame.components[c].h, frame.components[c].v),
+            };
+            scan.push((c, factors, dc, ac, table));
+        }
+
+        let (mcus_x, mcus_y) = match count {
+            1 => {
+                let (width, height) = frame.size(scan[0].0);
+                (width.div_ceil(8), height.div_ceil(8))
+            }
+            _ => (frame.mcus_x, frame.mcus_y),
+        };
+        if scan.iter().map(|&(_, (h, v), ..)| h * v).sum::<usize>() > 10 {
+            return Err(Error::InvalidHeader);
+        }
+
+        let (segments, length) = entropy_segments(data);
+        let mut segments = segments.iter();
+
+        let total = mcus_x * mcus_y;
+        let interval = match self.restart_interval {
+            0 => total,
+            n => n,
+        };
+
+        let mut reader = BitReader::new(&[][..]);
+        let mut prev_dc = vec![0; count];
+        for m in 0..total {
+            if m % interval == 0 {
+                reader = BitReader::new(&segments.next().ok_or(Error::Truncated)?[..]);
+                prev_dc.fill(0);
+            }
+
+            let (mx, my) = (m % mcus_x, m / mcus_x);
+            for (k, &(c, (h, v), ref dc, ref ac, table)) in scan.iter().enumerate() {
+                for y in 0..v {
+                    for x in 0..h {
+                        let block = decode_block(&mut reader, prev_dc[k], dc, ac)?;
+                        prev_dc[k] = block[0];
+
+                        let samples = Unit::new(block)
+                            .checked_inv_quantize(table)?
+                            .inv_zigzag()
+                            .inv_dct();
+                        frame.components[c].store(mx * h + x, my * v + y, samples);
+                    }
+                }
+            }
+        }
+
+        self.scans += 1;
+        Ok(length)
+    }
+
+    /// Upsamples the decoded components and converts them to RGB.
+    fn into_image(self) -> Result<Image<Rgb8>> {
+        let mut frame = match (self.frame, self.scans) {
+            (Some(frame), 1..) => frame,
+            _ => return Err(Error::Truncated),
+        };
+        let (width, height) = (frame.width, frame.height);
+
+        let planes: Vec<Image<i32>> = (0..frame.components.len())
+            .map(|c| {
+                let (w, h) = frame.size(c);
+                let component = &mut frame.components[c];
+
+                // Rows below the last decoded block are left at zero
+                let mut samples = std::mem::take(&mut component.samples);
+                samples.resize(h * component.stride, 0);
+                let plane = Image::new(component.stride, h, samples).crop(w, h);
+                let (h, v) = (frame.h_max / component.h, frame.v_max / component.v);
+
+                sampling::upsample(&plane, width, height, h, v, Upsampling::Bilinear)
+            })
+            .collect();
+
+        let model = match planes.len() {
+            1 => ColorModel::Gray,
+            _ => ColorModel::Bt601,
+        };
+
+        let data = (0..width * height)
+            .map(|i| {
+                let samples = std::array::from_fn(|c| planes.get(c).map_or(0, |p| p.data()[i]));
+                let [r, g, b] = transform::inverse(model, Precision::Bits8, samples);
+                Rgb8 { r: r as u8, g: g as u8, b: b as u8 }
+            })
+            .collect();
+
+        Ok(Image::new(width, height, data))
+    }
+}
+
+/// Reads the length prefixed segment at `pos`, returning its contents.
+fn segment<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a [u8]> {
+    let length = match data.get(*pos..*pos + 2) {
+        Some(&[a, b]) => u16::from_be_bytes([a, b]) as usize,
+        _ => return Err(Error::Truncated),
+    };
+    if length < 2 {
+        return Err(Error::InvalidHeader);
+    }
+
+    let contents = data.get(*pos + 2..*pos + length).ok_or(Error::Truncated)?;
+    *pos += length;
+
+    Ok(contents)
+}
+
+/// Reads the marker at `pos`, skipping fill bytes.
+fn next_marker(data: &[u8], pos: &mut usize) -> Result<u8> {
+    match data.get(*pos) {
+        Some(0xff) => {}
+        Some(_) => return Err(Error::InvalidData),
+        None => return Err(Error::Truncated),
+    }
+
+    while data.get(*pos) == Some(&0xff) {
+        *pos += 1;
+    }
+
+    let marker = *data.get(*pos).ok_or(Error::Truncated)?;
+    *pos += 1;
+
+    Ok(marker)
+}
+
+/// Splits entropy coded data at restart markers and removes the zero bytes
+/// stuffed after `0xff`. Returns the segments along with the length of the
+/// data, which ends at the first other marker.
+fn entropy_segments(data: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    let mut segments = vec![Vec::new()];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let segment = segments.last_mut().unwrap();
+        match (data[pos], data.get(pos + 1)) {
+            (0xff, Some(0x00)) => {
+                segment.push(0xff);
+                pos += 2;
+            }
+            (0xff, Some(&marker)) if (RST0..RST0 + 8).contains(&marker) => {
+                segments.push(Vec::new());
+                pos += 2;
+            }
+            (0xff, Some(0xff)) => pos += 1,
+            (0xff, _) => break,
+            (byte, _) => {
+                segment.push(byte);
+                pos += 1;
+            }
+        }
+    }
+
+    (segments, pos)
+}
+
+/// Decodes a baseline JPEG file. Other coding processes, such as progressive
+/// or arithmetic coded files, are reported as unsupported.
+pub fn decode<R>(mut input: R) -> Result<Image<Rgb8>>
+where R: Read
+{
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    if !data.starts_with(&[0xff, SOI]) {
+        return Err(Error::InvalidMagic);
+    }
+
+    let mut decoder = Decoder::default();
+    let mut pos = 2;
+    loop {
+        match next_marker(&data, &mut pos)? {
+            EOI => break,
+            SOF0 => {
+                if decoder.frame.is_some() {
+                    return Err(Error::InvalidHeader);
+                }
+                decoder.frame = Some(Frame::read(segment(&data, &mut pos)?)?);
+            }
+            SOF2 => return Err(Error::UnsupportedFeature("progressive JPEG")),
+            DHT => decoder.read_huffman(segment(&data, &mut pos)?)?,
+            // Start of frame markers of the other coding processes
+            marker @ 0xc1..=0xcf if marker != 0xc8 && marker != 0xcc => {
+                return Err(Error::UnsupportedFeature("JPEG coding process"));
+            }
+            DQT => decoder.read_quantization(segment(&data, &mut pos)?)?,
+            DRI => decoder.read_restart_interval(segment(&data, &mut pos)?)?,
+            SOS => {
+                let header = segment(&data, &mut pos)?;
+                pos += decoder.read_scan(header, &data[pos..])?;
+            }
+            // Markers without a segment, stray restart markers included
+            marker if marker == TEM || (RST0..RST0 + 8).contains(&marker) => {}
+            // Application data, comments and other segments are skipped
+            _ => {
+                segment(&data, &mut pos)?;
+            }
+        }
+    }
+
+    decoder.into_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ppm;
+    use crate::codec::rcr::entropy::{BitWriter, encode_block};
+    use crate::color::Gray8;
+    use crate::metrics;
+
+    /// Image the fixtures were encoded from at quality 90.
+    fn source() -> Image<Rgb8> {
+        ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap()
+            .crop(35, 21)
+    }
+
+    #[test]
+    fn fixtures_decode_close_to_source() {
+        let source = source();
+        let fixtures: [&[u8]; 3] = [
+            include_bytes!("../../../tests/fixtures/rgb_444.jpg"),
+            include_bytes!("../../../tests/fixtures/rgb_422.jpg"),
+            include_bytes!("../../../tests/fixtures/rgb_420.jpg"),
+        ];
+
+        for data in fixtures {
+            let decoded = decode(data).unwrap();
+
+            assert_eq!((decoded.width(), decoded.height()), (35, 21));
+            assert!(metrics::psnr(&source, &decoded).unwrap().combined > 33.0);
+        }
+    }
+
+    #[test]
+    fn grayscale_decodes_to_gray_pixels() {
+        let decoded = decode(&include_bytes!("../../../tests/fixtures/gray.jpg")[..]).unwrap();
+        assert!(decoded.data().iter().all(|p| p.r == p.g && p.g == p.b));
+
+        let expected: Image<Gray8> = source().convert();
+        let psnr = metrics::psnr(&expected, &decoded.convert()).unwrap().combined;
+        assert!(psnr > 35.0);
+    }
+
+    #[test]
+    fn restart_markers_do_not_change_samples() {
+        // Same image and quantization as `rgb_420.jpg`, with optimized
+        // Huffman tables and a restart marker every two MCUs
+        let plain = decode(&include_bytes!("../../../tests/fixtures/rgb_420.jpg")[..]).unwrap();
+        let restart = decode(&include_bytes!("../../../tests/fixtures/rgb_420_restart.jpg")[..]).unwrap();
+
+        assert_eq!(plain.data(), restart.data());
+    }
+
+    #[test]
+    fn standalone_markers_are_skipped() {
+        let data = include_bytes!("../../../tests/fixtures/rgb_444.jpg");
+        let expected = decode(&data[..]).unwrap();
+
+        let mut markers = data[..2].to_vec();
+        markers.extend([0xff, TEM, 0xff, RST0 + 3]);
+        markers.extend(&data[2..]);
+        assert_eq!(decode(&markers[..]).unwrap().data(), expected.data());
+    }
+
+    #[test]
+    fn decode_rejects_huge_frame_without_data() {
+        // The frame claims 65535x65535 pixels, with the scan of a 35x21 image
+        let mut data = include_bytes!("../../../tests/fixtures/gray.jpg").to_vec();
+        let sof = data.windows(2).position(|m| m == [0xff, SOF0]).unwrap();
+        data[sof + 5..sof + 9].fill(0xff);
+
+        assert!(matches!(decode(&data[..]), Err(Error::Truncated)));
+    }
+
+    #[test]
+    fn decode_rejects_overflowing_coefficients() {
+        // A single 8x8 gray block with a DC difference of 65535 and 16-bit
+        // quantizers of 65535, coded by hand
+        let mut block = [0; 64];
+        block[0] = u16::MAX as i32;
+
+        let mut dc_counts = [0; 256];
+        dc_counts[16] = 1;
+        let mut ac_counts = [0; 256];
+        ac_counts[0] = 1;
+        let dc = HuffmanTable::from_frequencies(&dc_counts);
+        let ac = HuffmanTable::from_frequencies(&ac_counts);
+
+        let mut writer = BitWriter::new();
+        encode_block(&mut writer, &block, 0, &dc, &ac);
+        let scan: Vec<u8> = writer
+            .into_bytes()
+            .into_iter()
+            .flat_map(|byte| match byte {
+                0xff => vec![0xff, 0x00],
+                _ => vec![byte],
+            })
+            .collect();
+
+        let mut tables = vec![0x00];
+        dc.write(&mut tables).unwrap();
+        tables.push(0x10);
+        ac.write(&mut tables).unwrap();
+
+        let mut data = vec![0xff, SOI];
+        data.extend([0xff, DQT, 0x00, 0x83, 0x10]);
+        data.extend([0xff; 128]);
+        data.extend([0xff, SOF0, 0x00, 0x0b, 8, 0, 8, 0, 8, 1, 1, 0x11, 0]);
+        data.extend([0xff, DHT]);
+        data.extend((tables.len() as u16 + 2).to_be_bytes());
+        data.extend(tables);
+        data.extend([0xff, SOS, 0x00, 0x08, 1, 1, 0x00, 0, 63, 0]);
+        data.extend(scan);
+        data.extend([0xff, EOI]);
+
+        assert!(matches!(decode(&data[..]), Err(Error::InvalidData)));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_files() {
+        let data = include_bytes!("../../../tests/fixtures/rgb_444.jpg");
+
+        assert!(matches!(decode(&b"P6\n1 1\n255\n"[..]), Err(Error::InvalidMagic)));
+        assert!(matches!(decode(&data[..data.len() / 2]), Err(Error::Truncated)));
+
+        let sof = data.windows(2).position(|w| w == [0xff, SOF0]).unwrap();
+        let mut progressive = data.to_vec();
+        progressive[sof + 1] = SOF2;
+        assert!(matches!(decode(&progressive[..]), Err(Error::UnsupportedFeature(_))));
+    }
+}
@@ -0,0 +1,343 @@
+use std::io::Write;
+
+use super::*;
+use crate::codec::rcr::entropy::{BitWriter, Frequencies, HuffmanTable, encode_block};
+use crate::codec::rcr::header::{ColorModel, Precision, Subsampling};
+use crate::codec::rcr::sampling;
+use crate::codec::rcr::tables;
+use crate::codec::rcr::transform;
+use crate::codec::rcr::unit::Unit;
+use crate::color::Rgb8;
+use crate::error::{Error, Result};
+use crate::image::Image;
+
+/// Code lengths and symbols of the example tables of the JPEG standard
+/// (Annex K.3), used unless optimized tables are requested.
+const DC_LUMA: ([u8; 16], &[u8]) = (
+    [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+);
+
+const DC_CHROMA: ([u8; 16], &[u8]) = (
+    [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+);
+
+const AC_LUMA: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+        0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+        0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+        0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+        0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+        0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+        0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+        0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+        0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+        0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+        0xf9, 0xfa,
+    ],
+);
+
+const AC_CHROMA: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+        0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+        0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+        0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+        0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+        0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+        0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+        0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+        0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+        0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+        0xf9, 0xfa,
+    ],
+);
+
+/// Largest quantizer of a baseline file, which stores 8-bit tables.
+const MAX_QUANTIZER: i32 = u8::MAX as i32;
+
+/// Largest magnitude of an AC coefficient the Huffman tables can code.
+const MAX_AC: i32 = 1023;
+
+/// Coding choices of `encode`. Chroma is subsampled 4:2:0 by default, like
+/// most JPEG encoders do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    luma_table: Unit<i32>,
+    chroma_table: Unit<i32>,
+    subsampling: Subsampling,
+    optimized_huffman: bool,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self::quality(75).unwrap()
+    }
+
+    /// Settings with the tables of `rcr::Settings::quality`, quantizers above
+    /// 255 are limited to fit a baseline file.
+    pub fn quality(quality: usize) -> Result<Self> {
+        let (luma_table, chroma_table) = tables::scaled(quality)?;
+        let limit = |table: Unit<i32>| table.convert(|x| x.min(MAX_QUANTIZER));
+
+        Self::with_tables(limit(luma_table), limit(chroma_table))
+    }
+
+    /// Settings with custom quantization tables, given in row-major order.
+    pub fn with_tables(luma_table: Unit<i32>, chroma_table: Unit<i32>) -> Result<Self> {
+        for table in [luma_table, chroma_table] {
+            if !table.into_iter().all(|x| (1..=MAX_QUANTIZER).contains(&x)) {
+                return Err(Error::InvalidTable);
+            }
+        }
+
+        Ok(Self {
+            luma_table,
+            chroma_table,
+            subsampling: Subsampling::S420,
+            optimized_huffman: false,
+        })
+    }
+
+    pub fn with_subsampling(mut self, subsampling: Subsampling) -> Self {
+        self.subsampling = subsampling;
+        self
+    }
+
+    /// Builds Huffman tables for the image instead of using the example
+    /// tables of the standard, which makes files smaller at the cost of a
+    /// second pass over the coefficients.
+    pub fn with_optimized_huffman(mut self, optimized_huffman: bool) -> Self {
+        self.optimized_huffman = optimized_huffman;
+        self
+    }
+
+    pub fn luma_table(&self) -> Unit<i32> {
+        self.luma_table
+    }
+
+    pub fn chroma_table(&self) -> Unit<i32> {
+        self.chroma_table
+    }
+
+    pub fn subsampling(&self) -> Subsampling {
+        self.subsampling
+    }
+
+    pub fn optimized_huffman(&self) -> bool {
+        self.optimized_huffman
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes a marker followed by a segment with the given contents.
+fn write_segment<T>(output: &mut T, marker: u8, contents: &[u8]) -> Result<()>
+where T: Write
+{
+    output.write_all(&[0xff, marker])?;
+    output.write_all(&(contents.len() as u16 + 2).to_be_bytes())?;
+    output.write_all(contents)?;
+
+    Ok(())
+}
+
+/// Inserts a zero byte after every `0xff` of entropy coded data, so that it
+/// cannot be mistaken for a marker.
+fn stuff(data: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 64);
+    for byte in data {
+        out.push(byte);
+        if byte == 0xff {
+            out.push(0x00);
+        }
+    }
+
+    out
+}
+
+/// Encodes the image as a baseline JFIF file with YCbCr components, in a
+/// single interleaved scan.
+pub fn encode<T>(mut output: T, settings: Settings, image: &Image<Rgb8>) -> Result<()>
+where T: Write
+{
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 || width > u16::MAX as usize || height > u16::MAX as usize {
+        return Err(Error::InvalidDimensions { width, height });
+    }
+
+    let samples = image.map(|p| {
+        transform::forward(ColorModel::Bt601, Precision::Bits8, [p.r, p.g, p.b].map(i32::from))
+    });
+    let (h, v) = settings.subsampling.factors();
+    let planes = [
+        samples.map(|x| x[0]),
+        sampling::downsample(&samples.map(|x| x[1]), h, v),
+        sampling::downsample(&samples.map(|x| x[2]), h, v),
+    ];
+
+    // Blocks in coding order, MCUs hold `h`x`v` luma blocks and one block of
+    // each chroma component
+    let (mcus_x, mcus_y) = (width.div_ceil(8 * h), height.div_ceil(8 * v));
+    let mut blocks = Vec::with_capacity(mcus_x * mcus_y * (h * v + 2));
+    for my in 0..mcus_y {
+        for mx in 0..mcus_x {
+            for y in 0..v {
+                for x in 0..h {
+                    blocks.push((0, planes[0].block(mx * h + x, my * v + y)));
+                }
+            }
+            blocks.push((1, planes[1].block(mx, my)));
+            blocks.push((2, planes[2].block(mx, my)));
+        }
+    }
+
+    let quantization = [settings.luma_table, settings.chroma_table];
+    let blocks: Vec<(usize, [i32; 64])> = blocks
+        .into_iter()
+        .map(|(c, block)| {
+            let table = quantization[c.min(1)].convert(|x| x as f32);
+            let coefficients = Unit::new(block)
+                .convert(|x| x as f32)
+                .fast_dct()
+                .quantize(table)
+                .convert(|x| (x as i32).clamp(-MAX_AC, MAX_AC))
+                .zigzag()
+                .unwrap();
+
+            (c, coefficients)
+        })
+        .collect();
+
+    let huffman = match settings.optimized_huffman {
+        true => {
+            let mut frequencies = [Frequencies::new(), Frequencies::new()];
+            let mut prev_dc = [0; 3];
+            for &(c, ref block) in &blocks {
+                frequencies[c.min(1)].count(block, prev_dc[c]);
+                prev_dc[c] = block[0];
+            }
+
+            frequencies.map(|f| (HuffmanTable::from_frequencies(&f.dc), HuffmanTable::from_frequencies(&f.ac)))
+        }
+        false => {
+            let table = |(counts, symbols): ([u8; 16], &[u8])| HuffmanTable::new(counts, symbols.to_vec()).unwrap();
+            [(table(DC_LUMA), table(AC_LUMA)), (table(DC_CHROMA), table(AC_CHROMA))]
+        }
+    };
+
+    output.write_all(&[0xff, SOI])?;
+
+    // JFIF 1.01 without units, pixel aspect ratio of 1 and no thumbnail
+    write_segment(&mut output, APP0, &[b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0])?;
+
+    let mut dqt = Vec::with_capacity(2 * 65);
+    for (slot, table) in quantization.iter().enumerate() {
+        dqt.push(slot as u8);
+        dqt.extend(table.zigzag().into_iter().map(|x| x as u8));
+    }
+    write_segment(&mut output, DQT, &dqt)?;
+
+    let mut sof = vec![8];
+    sof.extend((height as u16).to_be_bytes());
+    sof.extend((width as u16).to_be_bytes());
+    sof.extend([3, 1, (h << 4 | v) as u8, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    write_segment(&mut output, SOF0, &sof)?;
+
+    let mut dht = Vec::new();
+    for (slot, (dc, ac)) in huffman.iter().enumerate() {
+        dht.push(slot as u8);
+        dc.write(&mut dht)?;
+        dht.push(0x10 | slot as u8);
+        ac.write(&mut dht)?;
+    }
+    write_segment(&mut output, DHT, &dht)?;
+
+    write_segment(&mut output, SOS, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0])?;
+
+    let mut writer = BitWriter::new();
+    let mut prev_dc = [0; 3];
+    for &(c, ref block) in &blocks {
+        let (dc, ac) = &huffman[c.min(1)];
+        encode_block(&mut writer, block, prev_dc[c], dc, ac);
+        prev_dc[c] = block[0];
+    }
+    output.write_all(&stuff(writer.into_bytes()))?;
+
+    output.write_all(&[0xff, EOI])?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ppm;
+    use crate::metrics;
+
+    fn sample() -> Image<Rgb8> {
+        ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap()
+            .crop(181, 97)
+    }
+
+    #[test]
+    fn encoded_files_decode_close_to_source() {
+        let image = sample();
+
+        for subsampling in [Subsampling::S444, Subsampling::S422, Subsampling::S420] {
+            let mut data = Vec::new();
+            encode(&mut data, Settings::new().with_subsampling(subsampling), &image).unwrap();
+
+            assert_eq!(data[..2], [0xff, SOI]);
+            assert_eq!(data[6..11], *b"JFIF\0");
+            assert_eq!(data[data.len() - 2..], [0xff, EOI]);
+
+            let decoded = decode(&data[..]).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (181, 97));
+            assert!(metrics::psnr(&image, &decoded).unwrap().combined > 30.0);
+        }
+    }
+
+    #[test]
+    fn optimized_huffman_tables_shrink_files() {
+        let image = sample();
+
+        for quality in [1, 75, 100] {
+            let settings = Settings::quality(quality).unwrap();
+            let mut standard = Vec::new();
+            encode(&mut standard, settings, &image).unwrap();
+            let mut optimized = Vec::new();
+            encode(&mut optimized, settings.with_optimized_huffman(true), &image).unwrap();
+
+            assert!(optimized.len() < standard.len());
+            assert_eq!(decode(&optimized[..]).unwrap().data(), decode(&standard[..]).unwrap().data());
+        }
+    }
+
+    #[test]
+    fn quality_tables_fit_baseline() {
+        let settings = Settings::quality(1).unwrap();
+        assert!(settings.luma_table().into_iter().all(|x| x <= 255));
+        assert!(settings.chroma_table().into_iter().all(|x| x <= 255));
+
+        let wide = Unit::new([256; 64]);
+        assert!(matches!(Settings::with_tables(wide, wide), Err(Error::InvalidTable)));
+    }
+
+    #[test]
+    fn encode_rejects_unsupported_dimensions() {
+        let image = Image::new(70000, 1, vec![Rgb8 { r: 0, g: 0, b: 0 }; 70000]);
+        let result = encode(Vec::new(), Settings::new(), &image);
+
+        assert!(matches!(result, Err(Error::InvalidDimensions { width: 70000, height: 1 })));
+    }
+}
@@ -0,0 +1,43 @@
+//! Baseline JPEG (JFIF) files: sequential DCT with Huffman coding and 8-bit
+//! samples, in grayscale or YCbCr with any chroma subsampling. Blocks go
+//! through the same `Unit` machinery, quality tables and entropy coding as
+//! RCR.
+
+pub use decoder::decode;
+pub use encoder::{Settings, encode};
+
+mod decoder;
+mod encoder;
+
+/// Start of image.
+pub const SOI: u8 = 0xd8;
+
+/// End of image.
+pub const EOI: u8 = 0xd9;
+
+/// Start of a baseline sequential frame.
+pub const SOF0: u8 = 0xc0;
+
+/// Start of a progressive frame.
+pub const SOF2: u8 = 0xc2;
+
+/// Huffman table definition.
+pub const DHT: u8 = 0xc4;
+
+/// Quantization table definition.
+pub const DQT: u8 = 0xdb;
+
+/// Restart interval definition.
+pub const DRI: u8 = 0xdd;
+
+/// Start of scan, followed by the entropy coded data.
+pub const SOS: u8 = 0xda;
+
+/// First of the eight restart markers, which cycle from `RST0` to `RST7`.
+pub const RST0: u8 = 0xd0;
+
+/// Marker for private use, which stands alone like the restart markers.
+pub const TEM: u8 = 0x01;
+
+/// JFIF application segment.
+pub const APP0: u8 = 0xe0;
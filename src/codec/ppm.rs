@@ -1,8 +1,10 @@
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{Read, Write};
 
 use crate::image::Image;
-use crate::color::Rgb8;
+use crate::color::{Gray16, Gray8, Pixel, Rgb16, Rgb8, Rgba8};
+use crate::error::{Error, Result};
 
+#[derive(Default)]
 pub struct Ppm;
 
 impl Ppm {
@@ -11,57 +13,753 @@ impl Ppm {
     }
 }
 
-pub fn encode<T>(mut output: T, image: &Image<Rgb8>)
+/// Netpbm variant, named after its magic number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// ASCII bitmap (PBM)
+    P1,
+    /// ASCII graymap (PGM)
+    P2,
+    /// ASCII pixmap (PPM)
+    P3,
+    /// Binary bitmap (PBM)
+    P4,
+    /// Binary graymap (PGM)
+    P5,
+    /// Binary pixmap (PPM)
+    P6,
+    /// Binary arbitrary map (PAM) with 1 to 4 channels
+    P7,
+}
+
+impl Format {
+    fn from_magic(magic: &[u8]) -> Result<Self> {
+        match magic {
+            b"P1" => Ok(Format::P1),
+            b"P2" => Ok(Format::P2),
+            b"P3" => Ok(Format::P3),
+            b"P4" => Ok(Format::P4),
+            b"P5" => Ok(Format::P5),
+            b"P6" => Ok(Format::P6),
+            b"P7" => Ok(Format::P7),
+            _ => Err(Error::InvalidMagic),
+        }
+    }
+
+    fn magic(self) -> &'static str {
+        match self {
+            Format::P1 => "P1",
+            Format::P2 => "P2",
+            Format::P3 => "P3",
+            Format::P4 => "P4",
+            Format::P5 => "P5",
+            Format::P6 => "P6",
+            Format::P7 => "P7",
+        }
+    }
+
+    fn channels(self) -> usize {
+        match self {
+            Format::P3 | Format::P6 => 3,
+            _ => 1,
+        }
+    }
+
+    fn is_bitmap(self) -> bool {
+        matches!(self, Format::P1 | Format::P4)
+    }
+
+    fn is_ascii(self) -> bool {
+        matches!(self, Format::P1 | Format::P2 | Format::P3)
+    }
+}
+
+/// Decoded Netpbm raster, samples are stored interleaved at full depth.
+struct Raster {
+    width: usize,
+    height: usize,
+    channels: usize,
+    maxval: u32,
+    samples: Vec<u16>,
+}
+
+/// Byte cursor over the whole file, understanding Netpbm header syntax.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.data.get(self.pos) {
+            if c == b'#' {
+                while let Some(&c) = self.data.get(self.pos) {
+                    if c == b'\n' || c == b'\r' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            }
+            else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            else {
+                break;
+            }
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).ok_or(Error::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(Error::Truncated)?;
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+        while self.data.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            return match self.data.get(self.pos) {
+                None => Err(Error::Truncated),
+                Some(_) => Err(Error::InvalidHeader),
+            };
+        }
+
+        std::str::from_utf8(&self.data[start..self.pos])
+            .unwrap()
+            .parse()
+            .map_err(|_| Error::InvalidHeader)
+    }
+
+    /// Single `0` or `1` digit of an ASCII bitmap, which need no separators.
+    fn bit(&mut self) -> Result<u16> {
+        self.skip_whitespace();
+        match self.bytes(1)?[0] {
+            b'0' => Ok(0),
+            b'1' => Ok(1),
+            _ => Err(Error::InvalidHeader),
+        }
+    }
+
+    /// Whitespace separated token, such as a PAM header keyword.
+    fn word(&mut self) -> Result<&'a [u8]> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+        while self.data.get(self.pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+
+        match start == self.pos {
+            true => Err(Error::Truncated),
+            false => Ok(&self.data[start..self.pos]),
+        }
+    }
+
+    /// Consumes the single whitespace byte separating header and binary raster.
+    fn raster_separator(&mut self) -> Result<()> {
+        match self.bytes(1)?[0] {
+            c if c.is_ascii_whitespace() => Ok(()),
+            _ => Err(Error::InvalidHeader),
+        }
+    }
+}
+
+fn read_raster(data: &[u8]) -> Result<(Format, Raster)> {
+    let mut parser = Parser::new(data);
+    let format = Format::from_magic(parser.bytes(2).map_err(|_| Error::InvalidMagic)?)?;
+
+    let (width, height, channels, maxval) = match format {
+        Format::P7 => read_pam_header(&mut parser)?,
+        _ => {
+            let width = parser.number()? as usize;
+            let height = parser.number()? as usize;
+            let maxval = match format.is_bitmap() {
+                true => 1,
+                false => parser.number()?,
+            };
+
+            (width, height, format.channels(), maxval)
+        }
+    };
+
+    if width == 0 || height == 0 {
+        return Err(Error::InvalidDimensions { width, height });
+    }
+
+    if maxval == 0 || maxval > u16::MAX as u32 {
+        return Err(Error::UnsupportedMaxval(maxval));
+    }
+
+    let size = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+        .ok_or(Error::InvalidDimensions { width, height })?;
+
+    if !format.is_ascii() {
+        parser.raster_separator()?;
+    }
+
+    let samples: Vec<u16> = match format {
+        Format::P1 => (0..size)
+            .map(|_| parser.bit().map(|bit| 1 - bit))
+            .collect::<Result<_>>()?,
+        Format::P2 | Format::P3 => (0..size)
+            .map(|_| parser.number().map(|x| x.min(maxval) as u16))
+            .collect::<Result<_>>()?,
+        Format::P4 => {
+            let stride = width.div_ceil(8);
+            let bytes = parser.bytes(stride * height)?;
+            bytes
+                .chunks_exact(stride)
+                .flat_map(|row| (0..width).map(move |x| (row[x / 8] >> (7 - x % 8)) & 1))
+                .map(|bit| 1 - bit as u16)
+                .collect()
+        }
+        Format::P5 | Format::P6 | Format::P7 if maxval < 256 => parser
+            .bytes(size)?
+            .iter()
+            .map(|&x| (x as u32).min(maxval) as u16)
+            .collect(),
+        Format::P5 | Format::P6 | Format::P7 => parser
+            .bytes(size.checked_mul(2).ok_or(Error::InvalidDimensions { width, height })?)?
+            .chunks_exact(2)
+            .map(|x| (u16::from_be_bytes([x[0], x[1]]) as u32).min(maxval) as u16)
+            .collect(),
+    };
+
+    Ok((format, Raster {
+        width,
+        height,
+        channels,
+        maxval,
+        samples,
+    }))
+}
+
+/// Reads the `KEYWORD value` lines of a PAM header up to `ENDHDR`, returning
+/// width, height, depth and maxval. The tuple type is implied by the depth.
+fn read_pam_header(parser: &mut Parser) -> Result<(usize, usize, usize, u32)> {
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+
+    loop {
+        match parser.word()? {
+            b"WIDTH" => width = Some(parser.number()? as usize),
+            b"HEIGHT" => height = Some(parser.number()? as usize),
+            b"DEPTH" => depth = Some(parser.number()? as usize),
+            b"MAXVAL" => maxval = Some(parser.number()?),
+            b"TUPLTYPE" => {
+                parser.word()?;
+            }
+            b"ENDHDR" => break,
+            _ => return Err(Error::InvalidHeader),
+        }
+    }
+
+    let (width, height, depth, maxval) = match (width, height, depth, maxval) {
+        (Some(w), Some(h), Some(d), Some(m)) => (w, h, d, m),
+        _ => return Err(Error::InvalidHeader),
+    };
+
+    if !(1..=4).contains(&depth) {
+        return Err(Error::UnsupportedFeature("PAM depth"));
+    }
+
+    Ok((width, height, depth, maxval))
+}
+
+fn scale_to_u8(x: u16, maxval: u32) -> u8 {
+    match maxval {
+        255 => x as u8,
+        _ => ((x as u32 * 255 + maxval / 2) / maxval) as u8,
+    }
+}
+
+fn scale_to_u16(x: u16, maxval: u32) -> u16 {
+    ((x as u32 * u16::MAX as u32 + maxval / 2) / maxval) as u16
+}
+
+fn read<T>(mut input: T) -> Result<Raster>
+where T: Read
+{
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    let (_, raster) = read_raster(&data)?;
+    Ok(raster)
+}
+
+/// Expands gray, gray and alpha, RGB or RGBA samples into a pixel.
+fn rgba(p: &[u16], maxval: u32) -> Rgba8 {
+    let x = |i: usize| scale_to_u8(p[i], maxval);
+
+    match p.len() {
+        1 => Rgba8 { r: x(0), g: x(0), b: x(0), a: 255 },
+        2 => Rgba8 { r: x(0), g: x(0), b: x(0), a: x(1) },
+        3 => Rgba8 { r: x(0), g: x(1), b: x(2), a: 255 },
+        _ => Rgba8 { r: x(0), g: x(1), b: x(2), a: x(3) },
+    }
+}
+
+/// Netpbm headers cannot describe empty images, the parser rejects them.
+fn check_dimensions<P>(image: &Image<P>) -> Result<()> {
+    let (width, height) = (image.width(), image.height());
+    match width == 0 || height == 0 {
+        true => Err(Error::InvalidDimensions { width, height }),
+        false => Ok(()),
+    }
+}
+
+fn pam_header(width: usize, height: usize, depth: usize, tuple_type: &str) -> String {
+    format!(
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n",
+        width,
+        height,
+        depth,
+        tuple_type,
+    )
+}
+
+fn luma(p: &Rgb8) -> u8 {
+    Gray8::from(*p).v
+}
+
+/// Writes whitespace separated ASCII samples, keeping lines under 70 characters.
+fn write_ascii<T, I>(output: &mut T, samples: I, separator: &str) -> Result<()>
+where
+    T: Write,
+    I: Iterator<Item = String>,
+{
+    let mut line = String::new();
+    for sample in samples {
+        if !line.is_empty() && line.len() + separator.len() + sample.len() > 70 {
+            line.push('\n');
+            output.write_all(line.as_bytes())?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push_str(separator);
+        }
+        line.push_str(&sample);
+    }
+
+    line.push('\n');
+    output.write_all(line.as_bytes())?;
+
+    Ok(())
+}
+
+pub fn encode<T>(output: T, image: &Image<Rgb8>) -> Result<()>
+where T: Write
+{
+    encode_as(output, Format::P6, image)
+}
+
+/// Encodes the image as the given Netpbm variant. Graymaps store the luma of
+/// each pixel, bitmaps threshold it at half intensity.
+pub fn encode_as<T>(mut output: T, format: Format, image: &Image<Rgb8>) -> Result<()>
+where T: Write
+{
+    check_dimensions(image)?;
+    let (width, height) = (image.width(), image.height());
+
+    let header = match format {
+        Format::P7 => pam_header(width, height, 3, "RGB"),
+        _ if format.is_bitmap() => format!("{}\n{} {}\n", format.magic(), width, height),
+        _ => format!("{}\n{} {}\n255\n", format.magic(), width, height),
+    };
+    output.write_all(&header.into_bytes())?;
+
+    let pixels = image.data().iter();
+    let bits = || pixels.clone().map(|p| luma(p) < 128);
+
+    match format {
+        Format::P1 => {
+            for row in bits().collect::<Vec<_>>().chunks(width) {
+                let row = row.iter().map(|&bit| (bit as u8).to_string());
+                write_ascii(&mut output, row, "")?;
+            }
+        }
+        Format::P2 => write_ascii(&mut output, pixels.map(|p| luma(p).to_string()), " ")?,
+        Format::P3 => {
+            let samples = pixels.flat_map(|p| [p.r, p.g, p.b]).map(|x| x.to_string());
+            write_ascii(&mut output, samples, " ")?;
+        }
+        Format::P4 => {
+            let data: Vec<u8> = bits()
+                .collect::<Vec<_>>()
+                .chunks(width)
+                .flat_map(|row| row
+                    .chunks(8)
+                    .map(|byte| byte
+                        .iter()
+                        .enumerate()
+                        .fold(0, |acc, (i, &bit)| acc | ((bit as u8) << (7 - i)))
+                    )
+                    .collect::<Vec<_>>()
+                )
+                .collect();
+            output.write_all(&data)?;
+        }
+        Format::P5 => {
+            let data: Vec<u8> = pixels.map(luma).collect();
+            output.write_all(&data)?;
+        }
+        Format::P6 | Format::P7 => {
+            let data: Vec<u8> = pixels.flat_map(|p| [p.r, p.g, p.b]).collect();
+            output.write_all(&data)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes any of the P1-P7 Netpbm variants. Samples with a maxval other
+/// than 255 are rescaled to 8 bits, graymaps and bitmaps are expanded to gray
+/// pixels and alpha is dropped.
+pub fn decode<T>(input: T) -> Result<Image<Rgb8>>
+where T: Read
+{
+    Ok(decode_rgba(input)?.map(|&p| Rgb8::from(p)))
+}
+
+/// Encodes a grayscale image as a binary graymap (P5).
+pub fn encode_gray<T>(mut output: T, image: &Image<Gray8>) -> Result<()>
 where T: Write
 {
-    let header = format!("P6\n{:?} {}\n255\n", image.width(), image.height());
-    output.write(&header.into_bytes()).unwrap();
+    check_dimensions(image)?;
+
+    let header = format!("P5\n{} {}\n255\n", image.width(), image.height());
+    output.write_all(&header.into_bytes())?;
 
-    image
+    let data: Vec<u8> = image.data().iter().map(|p| p.v).collect();
+    output.write_all(&data)?;
+
+    Ok(())
+}
+
+/// Decodes any of the P1-P7 Netpbm variants into a grayscale image, pixmaps
+/// are reduced to their luma.
+pub fn decode_gray<T>(input: T) -> Result<Image<Gray8>>
+where T: Read
+{
+    Ok(decode_rgba(input)?.map(|&p| Gray8::from(Rgb8::from(p))))
+}
+
+/// Encodes an image with transparency as a PAM (P7) with the `RGB_ALPHA`
+/// tuple type.
+pub fn encode_rgba<T>(mut output: T, image: &Image<Rgba8>) -> Result<()>
+where T: Write
+{
+    check_dimensions(image)?;
+
+    let header = pam_header(image.width(), image.height(), 4, "RGB_ALPHA");
+    output.write_all(&header.into_bytes())?;
+
+    let data: Vec<u8> = image.data().iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect();
+    output.write_all(&data)?;
+
+    Ok(())
+}
+
+/// Decodes any of the P1-P7 Netpbm variants, pixels without an alpha channel
+/// are opaque.
+pub fn decode_rgba<T>(input: T) -> Result<Image<Rgba8>>
+where T: Read
+{
+    let raster = read(input)?;
+    let data: Vec<Rgba8> = raster.samples
+        .chunks_exact(raster.channels)
+        .map(|p| rgba(p, raster.maxval))
+        .collect();
+
+    Ok(Image::new(raster.width, raster.height, data))
+}
+
+/// Encodes a 16-bit image as a binary pixmap (P6) with maxval 65535.
+pub fn encode_rgb16<T>(mut output: T, image: &Image<Rgb16>) -> Result<()>
+where T: Write
+{
+    type Channel = <Rgb16 as Pixel>::Channel;
+    check_dimensions(image)?;
+
+    let header = format!("P6\n{} {}\n{}\n", image.width(), image.height(), Channel::MAX);
+    output.write_all(&header.into_bytes())?;
+
+    let data: Vec<u8> = image
         .data()
         .iter()
-        .for_each(|p| {
-            output.write(&[p.r, p.g, p.b]).unwrap();
-        });
+        .flat_map(|p| [p.r, p.g, p.b])
+        .flat_map(Channel::to_be_bytes)
+        .collect();
+    output.write_all(&data)?;
+
+    Ok(())
+}
+
+/// Encodes a 16-bit grayscale image as a binary graymap (P5) with maxval 65535.
+pub fn encode_gray16<T>(mut output: T, image: &Image<Gray16>) -> Result<()>
+where T: Write
+{
+    type Channel = <Gray16 as Pixel>::Channel;
+    check_dimensions(image)?;
+
+    let header = format!("P5\n{} {}\n{}\n", image.width(), image.height(), Channel::MAX);
+    output.write_all(&header.into_bytes())?;
+
+    let data: Vec<u8> = image.data().iter().flat_map(|p| Channel::to_be_bytes(p.v)).collect();
+    output.write_all(&data)?;
+
+    Ok(())
 }
 
+/// Decodes any of the P1-P7 Netpbm variants without reducing the depth,
+/// samples are rescaled to 16 bits and alpha is dropped.
+pub fn decode_rgb16<T>(input: T) -> Result<Image<Rgb16>>
+where T: Read
+{
+    let raster = read(input)?;
+    let data: Vec<Rgb16> = raster.samples
+        .chunks_exact(raster.channels)
+        .map(|p| {
+            let x = |i: usize| scale_to_u16(p[i], raster.maxval);
+            match p.len() {
+                1 | 2 => Rgb16 { r: x(0), g: x(0), b: x(0) },
+                _ => Rgb16 { r: x(0), g: x(1), b: x(2) },
+            }
+        })
+        .collect();
+
+    Ok(Image::new(raster.width, raster.height, data))
+}
 
-pub fn decode<T>(input: T) -> Image<Rgb8>
+/// Decodes any of the P1-P7 Netpbm variants into a 16-bit grayscale image,
+/// pixmaps are reduced to their luma.
+pub fn decode_gray16<T>(input: T) -> Result<Image<Gray16>>
 where T: Read
 {
-    let mut input = BufReader::new(input);
-
-    // read magic number
-    let mut str = String::new();
-    input.read_line(& mut str).unwrap();
-    assert!(str.trim().eq("P6"));
-
-    // read size
-    let mut str = String::new();
-    input.read_line(& mut str).unwrap();
-    let mut parts = str
-        .split_whitespace()
-        .map(|s| s.parse::<usize>().unwrap());
-    
-    let width = parts.next().unwrap();
-    let height = parts.next().unwrap();
-
-    // read bit depth number
-    let mut str = String::new();
-    input.read_line(& mut str).unwrap();
-    assert!(str.trim().eq("255"));
-
-    // read image data
-    let mut data: Vec<Rgb8> = Vec::with_capacity(width*height);
-    for _ in 0..(width*height) {
-        let mut buf :[u8; 3] = [0; 3];
-        input.read_exact(& mut buf).unwrap();
-        data.push(Rgb8 {
-            r: buf[0],
-            g: buf[1],
-            b: buf[2],
-        });
-    }
-
-    Image::new(width, height, data)
-}
\ No newline at end of file
+    Ok(decode_rgb16(input)?.map(|&p| Gray16::from(p)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(v: u8) -> Rgb8 {
+        Rgb8 { r: v, g: v, b: v }
+    }
+
+    #[test]
+    fn decode_rejects_invalid_magic() {
+        let data = b"P9\n1 1\n255\n0 0 0\n";
+        assert!(matches!(decode(&data[..]), Err(Error::InvalidMagic)));
+    }
+
+    #[test]
+    fn decode_rejects_unsupported_maxval() {
+        let data = b"P6\n1 1\n0\n\x00\x00\x00";
+        assert!(matches!(decode(&data[..]), Err(Error::UnsupportedMaxval(0))));
+
+        let data = b"P6\n1 1\n65536\n\x00\x00\x00";
+        assert!(matches!(decode(&data[..]), Err(Error::UnsupportedMaxval(65536))));
+    }
+
+    #[test]
+    fn decode_rejects_truncated_data() {
+        let data = b"P6\n2 2\n255\n\x00\x00\x00";
+        assert!(matches!(decode(&data[..]), Err(Error::Truncated)));
+
+        let data = b"P6\n2";
+        assert!(matches!(decode(&data[..]), Err(Error::Truncated)));
+
+        let data = b"P6\n2 x\n255\n";
+        assert!(matches!(decode(&data[..]), Err(Error::InvalidHeader)));
+    }
+
+    #[test]
+    fn decode_rejects_oversized_dimensions() {
+        // Fits in `usize` with one byte per sample, but not with two
+        let data = b"P6\n4294967295 1431655765\n65535\n";
+        let result = decode(&data[..]);
+        assert!(matches!(result, Err(Error::InvalidDimensions { width: 4294967295, height: 1431655765 })));
+    }
+
+    #[test]
+    fn decode_comments_and_whitespace() {
+        let data = b"P3 # gimp export\n# size\n2\t1 # w h\n255\n1 2 3   4\n5 6";
+        let image = decode(&data[..]).unwrap();
+
+        assert_eq!(image.data(), &vec![
+            Rgb8 { r: 1, g: 2, b: 3 },
+            Rgb8 { r: 4, g: 5, b: 6 },
+        ]);
+    }
+
+    #[test]
+    fn decode_bitmaps() {
+        let ascii = decode(&b"P1\n3 2\n010\n1 0 1"[..]).unwrap();
+        let binary = decode(&b"P4\n3 2\n\x40\xa0"[..]).unwrap();
+
+        let expected = vec![gray(255), gray(0), gray(255), gray(0), gray(255), gray(0)];
+        assert_eq!(ascii.data(), &expected);
+        assert_eq!(binary.data(), &expected);
+    }
+
+    #[test]
+    fn decode_graymaps_with_arbitrary_maxval() {
+        let ascii = decode(&b"P2\n3 1\n15\n0 15 7"[..]).unwrap();
+        assert_eq!(ascii.data(), &vec![gray(0), gray(255), gray(119)]);
+
+        let binary = decode(&b"P5\n2 1\n65535\n\xff\xff\x80\x00"[..]).unwrap();
+        assert_eq!(binary.data(), &vec![gray(255), gray(128)]);
+    }
+
+    #[test]
+    fn encode_decode_equality() {
+        let image = Image::new(3, 2, vec![
+            Rgb8 { r: 1, g: 2, b: 3 },
+            Rgb8 { r: 4, g: 5, b: 6 },
+            gray(0),
+            gray(255),
+            Rgb8 { r: 200, g: 100, b: 50 },
+            gray(17),
+        ]);
+
+        for format in [Format::P3, Format::P6] {
+            let mut data = Vec::new();
+            encode_as(&mut data, format, &image).unwrap();
+            assert_eq!(image.data(), decode(&data[..]).unwrap().data());
+        }
+
+        let grayscale: Vec<Rgb8> = image.data().iter().map(|p| gray(luma(p))).collect();
+        for format in [Format::P2, Format::P5] {
+            let mut data = Vec::new();
+            encode_as(&mut data, format, &image).unwrap();
+            assert_eq!(&grayscale, decode(&data[..]).unwrap().data());
+        }
+
+        let bitmap: Vec<Rgb8> = grayscale.iter().map(|p| gray(p.r / 128 * 255)).collect();
+        for format in [Format::P1, Format::P4] {
+            let mut data = Vec::new();
+            encode_as(&mut data, format, &image).unwrap();
+            assert_eq!(&bitmap, decode(&data[..]).unwrap().data());
+        }
+    }
+
+    #[test]
+    fn encode_rejects_empty_images() {
+        let image = Image::new(0, 2, Vec::new());
+
+        for format in [Format::P1, Format::P3, Format::P4, Format::P6, Format::P7] {
+            let result = encode_as(Vec::new(), format, &image);
+            assert!(matches!(result, Err(Error::InvalidDimensions { width: 0, height: 2 })));
+        }
+
+        let results = [
+            encode_gray(Vec::new(), &Image::new(0, 2, Vec::new())),
+            encode_rgba(Vec::new(), &Image::new(0, 2, Vec::new())),
+            encode_rgb16(Vec::new(), &Image::new(0, 2, Vec::new())),
+            encode_gray16(Vec::new(), &Image::new(0, 2, Vec::new())),
+        ];
+        for result in results {
+            assert!(matches!(result, Err(Error::InvalidDimensions { width: 0, height: 2 })));
+        }
+    }
+
+    #[test]
+    fn gray_encode_decode_equality() {
+        let image = Image::new(4, 2, (0..8).map(|i| Gray8 { v: i * 33 }).collect());
+
+        let mut data = Vec::new();
+        encode_gray(&mut data, &image).unwrap();
+        assert!(data.starts_with(b"P5\n4 2\n255\n"));
+        assert_eq!(data.len(), 11 + 8);
+        assert_eq!(decode_gray(&data[..]).unwrap().data(), image.data());
+
+        let color = decode_gray(&b"P3\n2 1\n255\n255 0 0 10 10 10"[..]).unwrap();
+        assert_eq!(color.data(), &vec![Gray8 { v: 54 }, Gray8 { v: 10 }]);
+    }
+
+    #[test]
+    fn pam_encode_decode_equality() {
+        let image = Image::new(2, 2, vec![
+            Rgba8 { r: 1, g: 2, b: 3, a: 0 },
+            Rgba8 { r: 4, g: 5, b: 6, a: 128 },
+            Rgba8 { r: 255, g: 0, b: 0, a: 255 },
+            Rgba8 { r: 7, g: 8, b: 9, a: 10 },
+        ]);
+
+        let mut data = Vec::new();
+        encode_rgba(&mut data, &image).unwrap();
+        assert!(data.starts_with(b"P7\nWIDTH 2\nHEIGHT 2\nDEPTH 4\nMAXVAL 255\n"));
+        assert_eq!(decode_rgba(&data[..]).unwrap().data(), image.data());
+
+        let rgb = image.map(|&p| Rgb8::from(p));
+        assert_eq!(decode(&data[..]).unwrap().data(), rgb.data());
+
+        let mut data = Vec::new();
+        encode_as(&mut data, Format::P7, &rgb).unwrap();
+        assert_eq!(decode(&data[..]).unwrap().data(), rgb.data());
+    }
+
+    #[test]
+    fn pam_header_variants() {
+        let data = b"P7\n# gray with alpha\nTUPLTYPE GRAYSCALE_ALPHA\nHEIGHT 1 WIDTH 2\nDEPTH 2\nMAXVAL 15\nENDHDR\n\x0f\x00\x00\x0f";
+        let image = decode_rgba(&data[..]).unwrap();
+        assert_eq!(image.data(), &vec![
+            Rgba8 { r: 255, g: 255, b: 255, a: 0 },
+            Rgba8 { r: 0, g: 0, b: 0, a: 255 },
+        ]);
+
+        let data = b"P7\nWIDTH 1\nHEIGHT 1\nMAXVAL 255\nENDHDR\n\x00";
+        assert!(matches!(decode_rgba(&data[..]), Err(Error::InvalidHeader)));
+
+        let data = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\nENDHDR\n\x00\x00\x00\x00\x00";
+        assert!(matches!(decode_rgba(&data[..]), Err(Error::UnsupportedFeature(_))));
+    }
+
+    #[test]
+    fn high_depth_encode_decode_equality() {
+        let image = Image::new(2, 2, vec![
+            Rgb16 { r: 0, g: 1, b: 2 },
+            Rgb16 { r: 256, g: 4095, b: 65535 },
+            Rgb16 { r: 12345, g: 54321, b: 32768 },
+            Rgb16 { r: 65534, g: 7, b: 1000 },
+        ]);
+
+        let mut data = Vec::new();
+        encode_rgb16(&mut data, &image).unwrap();
+        assert!(data.starts_with(b"P6\n2 2\n65535\n"));
+        assert_eq!(decode_rgb16(&data[..]).unwrap().data(), image.data());
+        assert_eq!(decode(&data[..]).unwrap().data()[1], Rgb8 { r: 1, g: 16, b: 255 });
+
+        let gray = image.map(|&p| Gray16::from(p));
+        let mut data = Vec::new();
+        encode_gray16(&mut data, &gray).unwrap();
+        assert_eq!(decode_gray16(&data[..]).unwrap().data(), gray.data());
+
+        // Lower depths are rescaled to the full 16-bit range
+        let data = b"P2\n3 1\n4095\n0 4095 2048";
+        let gray = decode_gray16(&data[..]).unwrap();
+        assert_eq!(gray.data(), &vec![Gray16 { v: 0 }, Gray16 { v: 65535 }, Gray16 { v: 32776 }]);
+    }
+
+    #[test]
+    fn decode_sample_image() {
+        let image = decode(&include_bytes!("../../examples/in_192x192.ppm")[..]).unwrap();
+
+        assert_eq!(image.width(), 192);
+        assert_eq!(image.height(), 192);
+    }
+}
@@ -0,0 +1,291 @@
+//! Fast 8x8 DCT following the factorization of Arai, Agui and Nakajima, with
+//! the butterflies of the floating point DCT of the IJG library. Both passes
+//! transform eight columns at once, using AVX2 or SSE2 when available and
+//! plain arrays otherwise. All paths perform the same operations in the same
+//! order, so they give identical results. The intrinsics are kept in `simd`,
+//! the one module allowed to use `unsafe`.
+//!
+//! Results differ from `Unit::dct` and `Unit::inv_dct` by less than 0.002 for
+//! 8-bit samples and 0.05 for 12-bit samples, mostly due to the rounded
+//! constants of the reference tables. The integer variants differ by at most
+//! 1 after rounding.
+
+// Constants are written as in the IJG sources
+#![allow(clippy::excessive_precision)]
+
+use std::f32::consts::{FRAC_1_SQRT_2, SQRT_2};
+use std::ops::{Add, Mul, Sub};
+
+#[cfg(target_arch = "x86_64")]
+mod simd;
+
+/// Scale of the AAN outputs relative to the orthonormal DCT, divided by the
+/// square root of 8: `sqrt(2) cos(k pi / 16) / sqrt(8)`, and `1 / sqrt(8)`
+/// for DC.
+const SCALE: [f32; 8] = [
+    0.353553391, 0.490392640, 0.461939766, 0.415734806,
+    0.353553391, 0.277785117, 0.191341716, 0.097545161,
+];
+
+/// Eight lanes of `f32`, one per column of a block.
+trait Lanes: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> {
+    fn splat(x: f32) -> Self;
+    fn load(x: &[f32]) -> Self;
+    fn store(self, x: &mut [f32]);
+}
+
+/// Plain array of eight lanes, for other architectures and for tests.
+#[cfg(any(not(target_arch = "x86_64"), test))]
+#[derive(Clone, Copy)]
+struct Scalar([f32; 8]);
+
+#[cfg(any(not(target_arch = "x86_64"), test))]
+impl Lanes for Scalar {
+    #[inline(always)]
+    fn splat(x: f32) -> Self {
+        Self([x; 8])
+    }
+
+    #[inline(always)]
+    fn load(x: &[f32]) -> Self {
+        let mut lanes = [0.0; 8];
+        lanes.copy_from_slice(&x[..8]);
+        Self(lanes)
+    }
+
+    #[inline(always)]
+    fn store(self, x: &mut [f32]) {
+        x[..8].copy_from_slice(&self.0);
+    }
+}
+
+#[cfg(any(not(target_arch = "x86_64"), test))]
+macro_rules! scalar_op {
+    ($trait:ident, $fn:ident, $op:tt) => {
+        impl $trait for Scalar {
+            type Output = Self;
+
+            #[inline(always)]
+            fn $fn(self, rhs: Self) -> Self {
+                let mut lanes = self.0;
+                lanes.iter_mut().zip(rhs.0).for_each(|(a, b)| *a $op b);
+                Self(lanes)
+            }
+        }
+    };
+}
+
+#[cfg(any(not(target_arch = "x86_64"), test))]
+scalar_op!(Add, add, +=);
+#[cfg(any(not(target_arch = "x86_64"), test))]
+scalar_op!(Sub, sub, -=);
+#[cfg(any(not(target_arch = "x86_64"), test))]
+scalar_op!(Mul, mul, *=);
+
+/// Unscaled 1D DCT of every lane over the eight vectors.
+#[inline(always)]
+fn forward_1d<V: Lanes>(d: [V; 8]) -> [V; 8] {
+    let c = |x: f32| V::splat(x);
+
+    let tmp0 = d[0] + d[7];
+    let tmp7 = d[0] - d[7];
+    let tmp1 = d[1] + d[6];
+    let tmp6 = d[1] - d[6];
+    let tmp2 = d[2] + d[5];
+    let tmp5 = d[2] - d[5];
+    let tmp3 = d[3] + d[4];
+    let tmp4 = d[3] - d[4];
+
+    // Even part
+    let tmp10 = tmp0 + tmp3;
+    let tmp13 = tmp0 - tmp3;
+    let tmp11 = tmp1 + tmp2;
+    let tmp12 = tmp1 - tmp2;
+
+    let z1 = (tmp12 + tmp13) * c(FRAC_1_SQRT_2);
+
+    let out0 = tmp10 + tmp11;
+    let out4 = tmp10 - tmp11;
+    let out2 = tmp13 + z1;
+    let out6 = tmp13 - z1;
+
+    // Odd part
+    let tmp10 = tmp4 + tmp5;
+    let tmp11 = tmp5 + tmp6;
+    let tmp12 = tmp6 + tmp7;
+
+    let z5 = (tmp10 - tmp12) * c(0.382683433);
+    let z2 = c(0.541196100) * tmp10 + z5;
+    let z4 = c(1.306562965) * tmp12 + z5;
+    let z3 = tmp11 * c(FRAC_1_SQRT_2);
+
+    let z11 = tmp7 + z3;
+    let z13 = tmp7 - z3;
+
+    [out0, z11 + z4, out2, z13 - z2, out4, z13 + z2, out6, z11 - z4]
+}
+
+/// Inverse of `forward_1d`, taking inputs scaled like its outputs.
+#[inline(always)]
+fn inverse_1d<V: Lanes>(d: [V; 8]) -> [V; 8] {
+    let c = |x: f32| V::splat(x);
+
+    // Even part
+    let tmp10 = d[0] + d[4];
+    let tmp11 = d[0] - d[4];
+    let tmp13 = d[2] + d[6];
+    let tmp12 = (d[2] - d[6]) * c(SQRT_2) - tmp13;
+
+    let tmp0 = tmp10 + tmp13;
+    let tmp3 = tmp10 - tmp13;
+    let tmp1 = tmp11 + tmp12;
+    let tmp2 = tmp11 - tmp12;
+
+    // Odd part
+    let z13 = d[5] + d[3];
+    let z10 = d[5] - d[3];
+    let z11 = d[1] + d[7];
+    let z12 = d[1] - d[7];
+
+    let tmp7 = z11 + z13;
+    let tmp11 = (z11 - z13) * c(SQRT_2);
+
+    let z5 = (z10 + z12) * c(1.847759065);
+    let tmp10 = c(1.082392200) * z12 - z5;
+    let tmp12 = c(-2.613125930) * z10 + z5;
+
+    let tmp6 = tmp12 - tmp7;
+    let tmp5 = tmp11 - tmp6;
+    let tmp4 = tmp10 + tmp5;
+
+    [
+        tmp0 + tmp7,
+        tmp1 + tmp6,
+        tmp2 + tmp5,
+        tmp3 - tmp4,
+        tmp3 + tmp4,
+        tmp2 - tmp5,
+        tmp1 - tmp6,
+        tmp0 - tmp7,
+    ]
+}
+
+fn transpose(block: &mut [f32; 64]) {
+    for y in 0..8 {
+        for x in y + 1..8 {
+            block.swap(x + 8 * y, y + 8 * x);
+        }
+    }
+}
+
+#[inline(always)]
+fn load<V: Lanes>(block: &[f32; 64]) -> [V; 8] {
+    std::array::from_fn(|i| V::load(&block[8 * i..]))
+}
+
+#[inline(always)]
+fn store<V: Lanes>(rows: [V; 8]) -> [f32; 64] {
+    let mut block = [0.0; 64];
+    for (i, row) in rows.into_iter().enumerate() {
+        row.store(&mut block[8 * i..]);
+    }
+
+    block
+}
+
+/// Multiplies row `i` and column `j` by `SCALE[i]` and `SCALE[j]`, the scale
+/// expected by `inverse_1d`. For outputs of `forward_1d`, multiplies by
+/// `1 / (8 SCALE[i])` and `1 / (8 SCALE[j])` instead.
+#[inline(always)]
+fn scale<V: Lanes>(rows: [V; 8], forward: bool) -> [V; 8] {
+    let factors = match forward {
+        true => SCALE.map(|x| 0.125 / x),
+        false => SCALE,
+    };
+    let columns = V::load(&factors);
+
+    std::array::from_fn(|i| rows[i] * V::splat(factors[i]) * columns)
+}
+
+/// Column pass, then row pass on the transposed block, finally scaled to the
+/// orthonormal DCT.
+#[inline(always)]
+fn forward_2d<V: Lanes>(block: &[f32; 64]) -> [f32; 64] {
+    let mut block = store(forward_1d::<V>(load(block)));
+    transpose(&mut block);
+
+    let mut block = store(scale(forward_1d::<V>(load(&block)), true));
+    transpose(&mut block);
+
+    block
+}
+
+#[inline(always)]
+fn inverse_2d<V: Lanes>(block: &[f32; 64]) -> [f32; 64] {
+    let mut block = store(inverse_1d::<V>(scale(load(block), false)));
+    transpose(&mut block);
+
+    let mut block = store(inverse_1d::<V>(load(&block)));
+    transpose(&mut block);
+
+    block
+}
+
+/// Orthonormal 2D DCT of an 8x8 block in row-major order, like `Unit::dct`.
+pub fn forward(block: &[f32; 64]) -> [f32; 64] {
+    #[cfg(target_arch = "x86_64")]
+    return simd::forward(block);
+
+    #[cfg(not(target_arch = "x86_64"))]
+    forward_2d::<Scalar>(block)
+}
+
+/// Inverse of `forward`, like `Unit::inv_dct`.
+pub fn inverse(block: &[f32; 64]) -> [f32; 64] {
+    #[cfg(target_arch = "x86_64")]
+    return simd::inverse(block);
+
+    #[cfg(not(target_arch = "x86_64"))]
+    inverse_2d::<Scalar>(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks of pseudo-random samples from -2048 to 2047.
+    fn blocks() -> impl Iterator<Item = [f32; 64]> {
+        let mut state: u32 = 0x2545f491;
+        (0..200).map(move |_| {
+            std::array::from_fn(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state % 4096) as f32 - 2048.0
+            })
+        })
+    }
+
+    #[test]
+    fn inverse_restores_blocks() {
+        for block in blocks() {
+            let restored = inverse(&forward(&block));
+            assert!(block.iter().zip(restored).all(|(a, b)| (a - b).abs() < 0.01));
+        }
+    }
+
+    #[test]
+    fn paths_give_identical_results() {
+        for block in blocks() {
+            let coefficients = forward_2d::<Scalar>(&block);
+            assert_eq!(inverse(&coefficients), inverse_2d::<Scalar>(&coefficients));
+            assert_eq!(forward(&block), coefficients);
+
+            #[cfg(target_arch = "x86_64")]
+            {
+                assert_eq!(forward_2d::<simd::Sse2>(&block), coefficients);
+                assert_eq!(inverse_2d::<simd::Sse2>(&coefficients), inverse_2d::<Scalar>(&coefficients));
+            }
+        }
+    }
+}
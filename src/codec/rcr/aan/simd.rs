@@ -0,0 +1,137 @@
+//! SSE2 and AVX2 lanes for the fast DCT. This is the only module of the crate
+//! allowed to use `unsafe`, and only for the intrinsics of `std::arch`.
+
+#![allow(unsafe_code)]
+
+use std::arch::x86_64::*;
+use std::ops::{Add, Mul, Sub};
+
+use super::{Lanes, forward_2d, inverse_2d};
+
+/// Two SSE registers of four lanes. SSE2 is part of every x86-64 CPU, which
+/// makes the intrinsics safe to use.
+#[derive(Clone, Copy)]
+pub struct Sse2(__m128, __m128);
+
+impl Lanes for Sse2 {
+    #[inline(always)]
+    fn splat(x: f32) -> Self {
+        // SAFETY: SSE2 is always available on x86-64
+        unsafe { Self(_mm_set1_ps(x), _mm_set1_ps(x)) }
+    }
+
+    #[inline(always)]
+    fn load(x: &[f32]) -> Self {
+        let x = &x[..8];
+        // SAFETY: SSE2 is always available, the slice holds eight values and
+        // unaligned loads are allowed
+        unsafe { Self(_mm_loadu_ps(x.as_ptr()), _mm_loadu_ps(x.as_ptr().add(4))) }
+    }
+
+    #[inline(always)]
+    fn store(self, x: &mut [f32]) {
+        let x = &mut x[..8];
+        // SAFETY: SSE2 is always available, the slice holds eight values and
+        // unaligned stores are allowed
+        unsafe {
+            _mm_storeu_ps(x.as_mut_ptr(), self.0);
+            _mm_storeu_ps(x.as_mut_ptr().add(4), self.1);
+        }
+    }
+}
+
+macro_rules! sse2_op {
+    ($trait:ident, $fn:ident, $intrinsic:ident) => {
+        impl $trait for Sse2 {
+            type Output = Self;
+
+            #[inline(always)]
+            fn $fn(self, rhs: Self) -> Self {
+                // SAFETY: SSE2 is always available on x86-64
+                unsafe { Self($intrinsic(self.0, rhs.0), $intrinsic(self.1, rhs.1)) }
+            }
+        }
+    };
+}
+
+sse2_op!(Add, add, _mm_add_ps);
+sse2_op!(Sub, sub, _mm_sub_ps);
+sse2_op!(Mul, mul, _mm_mul_ps);
+
+/// One AVX register of eight lanes. Only created by functions enabling AVX2,
+/// which are called after checking that the CPU supports it.
+#[derive(Clone, Copy)]
+struct Avx2(__m256);
+
+impl Lanes for Avx2 {
+    #[inline(always)]
+    fn splat(x: f32) -> Self {
+        // SAFETY: only reached from `forward_avx2` and `inverse_avx2`, which
+        // run on CPUs with AVX2
+        unsafe { Self(_mm256_set1_ps(x)) }
+    }
+
+    #[inline(always)]
+    fn load(x: &[f32]) -> Self {
+        let x = &x[..8];
+        // SAFETY: the CPU supports AVX2 as for `splat`, the slice holds eight
+        // values and unaligned loads are allowed
+        unsafe { Self(_mm256_loadu_ps(x.as_ptr())) }
+    }
+
+    #[inline(always)]
+    fn store(self, x: &mut [f32]) {
+        let x = &mut x[..8];
+        // SAFETY: the CPU supports AVX2 as for `splat`, the slice holds eight
+        // values and unaligned stores are allowed
+        unsafe { _mm256_storeu_ps(x.as_mut_ptr(), self.0) }
+    }
+}
+
+macro_rules! avx2_op {
+    ($trait:ident, $fn:ident, $intrinsic:ident) => {
+        impl $trait for Avx2 {
+            type Output = Self;
+
+            #[inline(always)]
+            fn $fn(self, rhs: Self) -> Self {
+                // SAFETY: values of `Avx2` only exist on CPUs with AVX2
+                unsafe { Self($intrinsic(self.0, rhs.0)) }
+            }
+        }
+    };
+}
+
+avx2_op!(Add, add, _mm256_add_ps);
+avx2_op!(Sub, sub, _mm256_sub_ps);
+avx2_op!(Mul, mul, _mm256_mul_ps);
+
+#[target_feature(enable = "avx2")]
+fn forward_avx2(block: &[f32; 64]) -> [f32; 64] {
+    forward_2d::<Avx2>(block)
+}
+
+#[target_feature(enable = "avx2")]
+fn inverse_avx2(block: &[f32; 64]) -> [f32; 64] {
+    inverse_2d::<Avx2>(block)
+}
+
+/// Like `aan::forward`, with AVX2 if the CPU supports it and SSE2 otherwise.
+pub fn forward(block: &[f32; 64]) -> [f32; 64] {
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        return unsafe { forward_avx2(block) };
+    }
+
+    forward_2d::<Sse2>(block)
+}
+
+/// Like `aan::inverse`, with AVX2 if the CPU supports it and SSE2 otherwise.
+pub fn inverse(block: &[f32; 64]) -> [f32; 64] {
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2
+        return unsafe { inverse_avx2(block) };
+    }
+
+    inverse_2d::<Sse2>(block)
+}
@@ -0,0 +1,80 @@
+use crate::error::Result;
+
+use super::lossless::predict;
+use super::unit::Unit;
+
+/// Coding of the alpha plane of images with transparency.
+// The table is stored inline so that `Settings` stays `Copy`
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Alpha {
+    /// Predictive coding, alpha is reconstructed exactly
+    #[default]
+    Lossless,
+    /// Transform coding like the luma plane, with its own quantization table
+    Lossy(Unit<i32>),
+}
+
+/// Prediction of the first sample of the plane.
+const OPAQUE: i32 = u8::MAX as i32;
+
+/// Prediction errors of the samples from `start` on, wrapped to the range from
+/// -128 to 127. Samples before `start` only serve as context, which lets the
+/// plane be coded in bands.
+pub fn residuals(samples: &[u8], width: usize, start: usize) -> Vec<i32> {
+    (start..samples.len())
+        .map(|i| (samples[i] as i32 - predict(samples, width, i, OPAQUE) + 128).rem_euclid(256) - 128)
+        .collect()
+}
+
+/// Inverse of `residuals`, appending `count` samples to those already in
+/// `samples` with prediction errors pulled from `next`.
+pub fn reconstruct<F>(samples: &mut Vec<u8>, width: usize, count: usize, mut next: F) -> Result<()>
+where F: FnMut() -> Result<i32>
+{
+    for _ in 0..count {
+        let x = (predict(samples, width, samples.len(), OPAQUE) + next()?).rem_euclid(256);
+        samples.push(x as u8);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn residuals_round_trip() {
+        let (width, height) = (13, 7);
+        let samples: Vec<u8> = (0..width * height)
+            .map(|i| match (i % width, i / width) {
+                (x, y) if x + y < 6 => 0,
+                (x, _) if x > 10 => 255,
+                (x, y) => (x * 37 + y * 11) as u8,
+            })
+            .collect();
+
+        let all = residuals(&samples, width, 0);
+        assert!(all.iter().all(|r| (-128..128).contains(r)));
+
+        let mut iter = all.iter();
+        let mut decoded = Vec::new();
+        reconstruct(&mut decoded, width, width * height, || Ok(*iter.next().unwrap())).unwrap();
+        assert_eq!(decoded, samples);
+
+        // Coding in bands gives the same prediction errors
+        let band = residuals(&samples, width, 3 * width);
+        assert_eq!(band, all[3 * width..]);
+    }
+
+    #[test]
+    fn flat_regions_are_predicted() {
+        let samples = [255; 16];
+        assert!(residuals(&samples, 4, 0).iter().all(|&r| r == 0));
+
+        let edge: Vec<u8> = (0..16).map(|i| if i % 4 < 2 { 0 } else { 255 }).collect();
+        let residuals = residuals(&edge, 4, 0);
+        assert!(residuals[4..].iter().all(|&r| r == 0));
+    }
+}
@@ -0,0 +1,622 @@
+use std::io::{Read, Write};
+use std::ops::Range;
+
+use crate::error::{Error, Result};
+
+const MAX_CODE_LENGTH: usize = 16;
+const EOB: u8 = 0x00;
+const ZRL: u8 = 0xf0;
+
+/// Packs variable length codes MSB first into bytes.
+#[derive(Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_bits(&mut self, value: u32, len: u8) {
+        if len == 0 {
+            return;
+        }
+
+        self.acc = (self.acc << len) | (value as u64 & ((1 << len) - 1));
+        self.bits += len as u32;
+
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.data.push((self.acc >> self.bits) as u8);
+        }
+    }
+
+    /// Removes and returns the completed bytes, keeping any partial byte.
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+
+    /// Pads the last byte with one bits, so that the next code starts on a
+    /// byte boundary.
+    pub fn pad(&mut self) {
+        if self.bits > 0 {
+            let pad = 8 - self.bits as u8;
+            self.write_bits(u32::MAX, pad);
+        }
+    }
+
+    /// Pads the last byte and returns the packed data.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.pad();
+        self.data
+    }
+}
+
+/// Reads bits MSB first, pulling bytes from the input only when needed so
+/// that nothing past the coded data is consumed.
+pub struct BitReader<R> {
+    input: R,
+    acc: u8,
+    bits: u32,
+}
+
+impl<R> BitReader<R>
+where R: Read
+{
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn read_bit(&mut self) -> Result<u32> {
+        if self.bits == 0 {
+            let mut byte = [0; 1];
+            self.input.read_exact(&mut byte)?;
+            self.acc = byte[0];
+            self.bits = 8;
+        }
+
+        self.bits -= 1;
+        Ok(((self.acc >> self.bits) & 1) as u32)
+    }
+
+    pub fn read_bits(&mut self, len: u8) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..len {
+            value = (value << 1) | self.read_bit()?;
+        }
+
+        Ok(value)
+    }
+
+    /// Drops the padding bits of the current byte.
+    pub fn align(&mut self) {
+        self.bits = 0;
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+/// Canonical Huffman table in the JPEG representation: the number of codes of
+/// each length from 1 to 16 followed by the symbols ordered by code length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HuffmanTable {
+    counts: [u8; MAX_CODE_LENGTH],
+    symbols: Vec<u8>,
+    codes: Vec<(u16, u8)>,
+    max_code: [i32; MAX_CODE_LENGTH + 1],
+    offsets: [i32; MAX_CODE_LENGTH + 1],
+}
+
+impl HuffmanTable {
+    pub fn new(counts: [u8; MAX_CODE_LENGTH], symbols: Vec<u8>) -> Result<Self> {
+        let total: usize = counts.iter().map(|&n| n as usize).sum();
+        if total != symbols.len() || total > 256 {
+            return Err(Error::InvalidData);
+        }
+
+        let mut codes = vec![(0, 0); 256];
+        let mut max_code = [-1; MAX_CODE_LENGTH + 1];
+        let mut offsets = [0; MAX_CODE_LENGTH + 1];
+
+        let mut code: u32 = 0;
+        let mut k = 0;
+        for len in 1..=MAX_CODE_LENGTH {
+            let n = counts[len - 1] as usize;
+            offsets[len] = k as i32 - code as i32;
+
+            for &symbol in &symbols[k..k + n] {
+                codes[symbol as usize] = (code as u16, len as u8);
+                code += 1;
+            }
+
+            if n > 0 {
+                max_code[len] = code as i32 - 1;
+            }
+
+            // Codes of a given length must not run out of the available space
+            if code > 1 << len {
+                return Err(Error::InvalidData);
+            }
+
+            k += n;
+            code <<= 1;
+        }
+
+        Ok(Self {
+            counts,
+            symbols,
+            codes,
+            max_code,
+            offsets,
+        })
+    }
+
+    /// Builds a length limited optimal code for the given symbol frequencies,
+    /// following the procedure of the JPEG standard (Annex K.2).
+    pub fn from_frequencies(frequencies: &[u32; 256]) -> Self {
+        // Reserved symbol guarantees no code consists of one bits only
+        let mut freq = [0u64; 257];
+        freq[..256].iter_mut().zip(frequencies).for_each(|(a, &b)| *a = b as u64);
+        freq[256] = 1;
+
+        let mut code_size = [0usize; 257];
+        let mut others = [None; 257];
+
+        loop {
+            let smallest = |exclude: Option<usize>| (0..257)
+                .filter(|&i| freq[i] > 0 && Some(i) != exclude)
+                .min_by_key(|&i| (freq[i], std::cmp::Reverse(i)));
+
+            let v1 = match smallest(None) {
+                Some(v) => v,
+                None => break,
+            };
+            let v2 = match smallest(Some(v1)) {
+                Some(v) => v,
+                None => break,
+            };
+
+            freq[v1] += freq[v2];
+            freq[v2] = 0;
+
+            let mut v = v1;
+            loop {
+                code_size[v] += 1;
+                match others[v] {
+                    Some(next) => v = next,
+                    None => break,
+                }
+            }
+            others[v] = Some(v2);
+
+            let mut v = v2;
+            loop {
+                code_size[v] += 1;
+                match others[v] {
+                    Some(next) => v = next,
+                    None => break,
+                }
+            }
+        }
+
+        let mut bits = [0usize; 33];
+        code_size
+            .iter()
+            .filter(|&&size| size > 0)
+            .for_each(|&size| bits[size.min(32)] += 1);
+
+        // Limit code lengths to 16 bits
+        let mut i = 32;
+        while i > MAX_CODE_LENGTH {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+            i -= 1;
+        }
+
+        // Remove the reserved symbol
+        while bits[i] == 0 {
+            i -= 1;
+        }
+        bits[i] -= 1;
+
+        let mut counts = [0; MAX_CODE_LENGTH];
+        counts.iter_mut().zip(&bits[1..]).for_each(|(a, &b)| *a = b as u8);
+
+        let mut symbols: Vec<u8> = (0..256)
+            .filter(|&i| code_size[i] > 0)
+            .map(|i| i as u8)
+            .collect();
+        symbols.sort_by_key(|&s| code_size[s as usize]);
+
+        Self::new(counts, symbols).unwrap()
+    }
+
+    pub fn read<R>(input: &mut R) -> Result<Self>
+    where R: Read
+    {
+        let mut counts = [0; MAX_CODE_LENGTH];
+        input.read_exact(&mut counts)?;
+
+        let total: usize = counts.iter().map(|&n| n as usize).sum();
+        let mut symbols = vec![0; total];
+        input.read_exact(&mut symbols)?;
+
+        Self::new(counts, symbols)
+    }
+
+    pub fn write<W>(&self, output: &mut W) -> Result<()>
+    where W: Write
+    {
+        output.write_all(&self.counts)?;
+        output.write_all(&self.symbols)?;
+
+        Ok(())
+    }
+
+    pub fn encode(&self, writer: &mut BitWriter, symbol: u8) {
+        let (code, len) = self.codes[symbol as usize];
+        debug_assert!(len > 0, "symbol {:#04x} missing from huffman table", symbol);
+        writer.write_bits(code as u32, len);
+    }
+
+    pub fn decode<R>(&self, reader: &mut BitReader<R>) -> Result<u8>
+    where R: Read
+    {
+        let mut code = 0;
+        for len in 1..=MAX_CODE_LENGTH {
+            code = (code << 1) | reader.read_bit()? as i32;
+            if code <= self.max_code[len] {
+                return Ok(self.symbols[(code + self.offsets[len]) as usize]);
+            }
+        }
+
+        Err(Error::InvalidData)
+    }
+}
+
+/// Number of bits needed to represent the magnitude of `x`.
+pub fn magnitude_category(x: i32) -> u8 {
+    (32 - x.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Extra bits following a magnitude category, negative values are stored as
+/// their one's complement.
+fn magnitude_bits(x: i32, size: u8) -> u32 {
+    match x < 0 {
+        true => (x - 1) as u32 & ((1 << size) - 1),
+        false => x as u32,
+    }
+}
+
+fn extend(bits: u32, size: u8) -> i32 {
+    match size {
+        0 => 0,
+        _ if bits < 1 << (size - 1) => bits as i32 - (1 << size) + 1,
+        _ => bits as i32,
+    }
+}
+
+/// Table a coded symbol belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Dc,
+    Ac,
+}
+
+/// Splits a zigzag ordered block into symbols: the DC difference category
+/// followed by (run, size) pairs of AC coefficients and an end-of-block marker.
+/// Each symbol is passed along with its extra bits.
+pub fn block_symbols<F>(block: &[i32; 64], prev_dc: i32, f: F)
+where F: FnMut(Class, u8, u32, u8)
+{
+    scan_symbols(block, prev_dc, 0..64, f);
+}
+
+/// Like `block_symbols`, limited to the coefficients in `spectrum`. The DC
+/// difference is only included if the range starts at zero, the end-of-block
+/// marker ends the range.
+pub fn scan_symbols<F>(block: &[i32; 64], prev_dc: i32, spectrum: Range<usize>, mut f: F)
+where F: FnMut(Class, u8, u32, u8)
+{
+    if spectrum.start == 0 {
+        let diff = block[0] - prev_dc;
+        let size = magnitude_category(diff);
+        f(Class::Dc, size, magnitude_bits(diff, size), size);
+    }
+
+    let mut run = 0;
+    for &x in &block[spectrum.start.max(1)..spectrum.end] {
+        if x == 0 {
+            run += 1;
+            continue;
+        }
+
+        while run > 15 {
+            f(Class::Ac, ZRL, 0, 0);
+            run -= 16;
+        }
+
+        let size = magnitude_category(x);
+        f(Class::Ac, (run << 4) | size, magnitude_bits(x, size), size);
+        run = 0;
+    }
+
+    if run > 0 {
+        f(Class::Ac, EOB, 0, 0);
+    }
+}
+
+/// Symbol frequencies for a pair of DC and AC tables.
+pub struct Frequencies {
+    pub dc: [u32; 256],
+    pub ac: [u32; 256],
+}
+
+impl Frequencies {
+    pub fn new() -> Self {
+        Self {
+            dc: [0; 256],
+            ac: [0; 256],
+        }
+    }
+
+    pub fn count(&mut self, block: &[i32; 64], prev_dc: i32) {
+        self.count_scan(block, prev_dc, 0..64);
+    }
+
+    pub fn count_scan(&mut self, block: &[i32; 64], prev_dc: i32, spectrum: Range<usize>) {
+        scan_symbols(block, prev_dc, spectrum, |class, symbol, _, _| match class {
+            Class::Dc => self.dc[symbol as usize] += 1,
+            Class::Ac => self.ac[symbol as usize] += 1,
+        });
+    }
+}
+
+impl Default for Frequencies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Codes a single difference as its magnitude category followed by the extra
+/// bits, the same way as the DC coefficient of a block.
+pub fn encode_difference(writer: &mut BitWriter, diff: i32, table: &HuffmanTable) {
+    let size = magnitude_category(diff);
+    table.encode(writer, size);
+    writer.write_bits(magnitude_bits(diff, size), size);
+}
+
+pub fn decode_difference<R>(reader: &mut BitReader<R>, table: &HuffmanTable) -> Result<i32>
+where R: Read
+{
+    let size = table.decode(reader)?;
+    if size > 16 {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(extend(reader.read_bits(size)?, size))
+}
+
+pub fn encode_block(
+    writer: &mut BitWriter,
+    block: &[i32; 64],
+    prev_dc: i32,
+    dc: &HuffmanTable,
+    ac: &HuffmanTable,
+) {
+    encode_scan(writer, block, prev_dc, 0..64, dc, ac);
+}
+
+/// Codes the coefficients of a block in `spectrum`, see `scan_symbols`.
+pub fn encode_scan(
+    writer: &mut BitWriter,
+    block: &[i32; 64],
+    prev_dc: i32,
+    spectrum: Range<usize>,
+    dc: &HuffmanTable,
+    ac: &HuffmanTable,
+) {
+    scan_symbols(block, prev_dc, spectrum, |class, symbol, bits, len| {
+        match class {
+            Class::Dc => dc.encode(writer, symbol),
+            Class::Ac => ac.encode(writer, symbol),
+        }
+        writer.write_bits(bits, len);
+    });
+}
+
+pub fn decode_block<R>(
+    reader: &mut BitReader<R>,
+    prev_dc: i32,
+    dc: &HuffmanTable,
+    ac: &HuffmanTable,
+) -> Result<[i32; 64]>
+where R: Read
+{
+    let mut block = [0; 64];
+    decode_scan(reader, &mut block, prev_dc, 0..64, dc, ac)?;
+
+    Ok(block)
+}
+
+/// Decodes the coefficients of a block in `spectrum` into `block`, leaving
+/// the others as they are.
+pub fn decode_scan<R>(
+    reader: &mut BitReader<R>,
+    block: &mut [i32; 64],
+    prev_dc: i32,
+    spectrum: Range<usize>,
+    dc: &HuffmanTable,
+    ac: &HuffmanTable,
+) -> Result<()>
+where R: Read
+{
+    if spectrum.start == 0 {
+        block[0] = prev_dc
+            .checked_add(decode_difference(reader, dc)?)
+            .ok_or(Error::InvalidData)?;
+    }
+
+    let mut k = spectrum.start.max(1);
+    while k < spectrum.end {
+        let symbol = ac.decode(reader)?;
+        let run = (symbol >> 4) as usize;
+        let size = symbol & 0x0f;
+
+        match (run, size) {
+            (0, 0) => break,
+            (15, 0) => k += 16,
+            (_, 0) => return Err(Error::InvalidData),
+            _ => {
+                k += run;
+                if k >= spectrum.end {
+                    return Err(Error::InvalidData);
+                }
+                block[k] = extend(reader.read_bits(size)?, size);
+                k += 1;
+            }
+        }
+    }
+
+    if k > spectrum.end {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magnitude_coding_equality() {
+        for x in -2048..2048 {
+            let size = magnitude_category(x);
+            assert_eq!(extend(magnitude_bits(x, size), size), x);
+        }
+    }
+
+    #[test]
+    fn huffman_lengths_are_limited() {
+        // Fibonacci frequencies produce the deepest possible tree
+        let mut frequencies = [0; 256];
+        let (mut a, mut b) = (1, 1);
+        for f in frequencies.iter_mut().take(30) {
+            *f = a;
+            (a, b) = (b, a + b);
+        }
+
+        let table = HuffmanTable::from_frequencies(&frequencies);
+        assert_eq!(table.symbols.len(), 30);
+        assert!(table.codes.iter().all(|&(_, len)| len as usize <= MAX_CODE_LENGTH));
+    }
+
+    #[test]
+    fn block_coding_equality() {
+        let mut block = [0; 64];
+        block[0] = -300;
+        block[1] = 27;
+        block[5] = -1;
+        block[40] = 1023;
+        block[63] = -4;
+
+        let mut frequencies = Frequencies::new();
+        frequencies.count(&block, 12);
+        frequencies.count(&[0; 64], -300);
+
+        let dc = HuffmanTable::from_frequencies(&frequencies.dc);
+        let ac = HuffmanTable::from_frequencies(&frequencies.ac);
+
+        let mut writer = BitWriter::new();
+        encode_block(&mut writer, &block, 12, &dc, &ac);
+        encode_block(&mut writer, &[0; 64], -300, &dc, &ac);
+        let data = writer.into_bytes();
+
+        let mut reader = BitReader::new(&data[..]);
+        assert_eq!(decode_block(&mut reader, 12, &dc, &ac).unwrap(), block);
+        assert_eq!(decode_block(&mut reader, -300, &dc, &ac).unwrap(), [0; 64]);
+    }
+
+    #[test]
+    fn dc_prediction_overflow_is_rejected() {
+        let mut block = [0; 64];
+        block[0] = 40000;
+
+        let mut frequencies = Frequencies::new();
+        frequencies.count(&block, 0);
+        let dc = HuffmanTable::from_frequencies(&frequencies.dc);
+        let ac = HuffmanTable::from_frequencies(&frequencies.ac);
+
+        let mut writer = BitWriter::new();
+        encode_block(&mut writer, &block, 0, &dc, &ac);
+        let data = writer.into_bytes();
+
+        let mut reader = BitReader::new(&data[..]);
+        let result = decode_block(&mut reader, i32::MAX - 100, &dc, &ac);
+        assert!(matches!(result, Err(Error::InvalidData)));
+    }
+
+    #[test]
+    fn scan_coding_equality() {
+        let mut block = [0; 64];
+        block[0] = 40;
+        block[2] = -3;
+        block[6] = 9;
+        block[30] = 1;
+
+        let scans = [0..1, 1..6, 6..15, 15..64];
+        let mut frequencies = Frequencies::new();
+        for scan in scans.clone() {
+            frequencies.count_scan(&block, -8, scan);
+        }
+
+        let dc = HuffmanTable::from_frequencies(&frequencies.dc);
+        let ac = HuffmanTable::from_frequencies(&frequencies.ac);
+
+        let mut writer = BitWriter::new();
+        for scan in scans.clone() {
+            encode_scan(&mut writer, &block, -8, scan, &dc, &ac);
+        }
+        let data = writer.into_bytes();
+
+        let mut reader = BitReader::new(&data[..]);
+        let mut decoded = [0; 64];
+        for scan in scans {
+            decode_scan(&mut reader, &mut decoded, -8, scan.clone(), &dc, &ac).unwrap();
+            assert_eq!(decoded[..scan.end], block[..scan.end]);
+        }
+    }
+
+    #[test]
+    fn table_serialization_equality() {
+        let mut frequencies = [0; 256];
+        frequencies[0] = 100;
+        frequencies[3] = 10;
+        frequencies[0xf0] = 1;
+
+        let table = HuffmanTable::from_frequencies(&frequencies);
+        let mut data = Vec::new();
+        table.write(&mut data).unwrap();
+
+        assert_eq!(HuffmanTable::read(&mut &data[..]).unwrap(), table);
+    }
+}
@@ -0,0 +1,346 @@
+use std::io::{Read, Write};
+
+use crate::error::{Error, Result};
+
+pub const MAGIC: [u8; 4] = *b"RCR\x1a";
+
+/// Latest format version understood by this library.
+pub const VERSION: u8 = 1;
+
+/// Quantization tables are stored with 16-bit instead of 8-bit entries.
+pub const FLAG_WIDE_TABLES: u8 = 1 << 0;
+
+/// An alpha plane is coded after the color planes.
+pub const FLAG_ALPHA: u8 = 1 << 1;
+
+/// The alpha plane is predictively coded instead of transformed, only valid
+/// together with `FLAG_ALPHA`.
+pub const FLAG_LOSSLESS_ALPHA: u8 = 1 << 2;
+
+/// Color planes use 12-bit instead of 8-bit samples.
+pub const FLAG_12_BIT: u8 = 1 << 3;
+
+/// Coefficients are coded in several scans of spectral bands, listed after
+/// the tables, instead of block by block.
+pub const FLAG_PROGRESSIVE: u8 = 1 << 4;
+
+/// Blocks are transformed with the fixed-point DCT, which decoders have to
+/// invert exactly.
+pub const FLAG_INTEGER_DCT: u8 = 1 << 5;
+
+/// Color planes are predictively coded instead of transformed, only valid
+/// with the YCoCg-R or grayscale model at full resolution, sequential coding
+/// and, if there is one, a lossless alpha plane.
+pub const FLAG_LOSSLESS: u8 = 1 << 6;
+
+/// Mask of the feature flags known to this version, any other bit set in a
+/// file means it needs a newer decoder.
+const KNOWN_FLAGS: u8 = FLAG_WIDE_TABLES
+    | FLAG_ALPHA
+    | FLAG_LOSSLESS_ALPHA
+    | FLAG_12_BIT
+    | FLAG_PROGRESSIVE
+    | FLAG_INTEGER_DCT
+    | FLAG_LOSSLESS;
+
+/// Transform from RGB into the coded luma and chroma planes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorModel {
+    /// Full range YCbCr with BT.709 coefficients, as in `color::Lab8`
+    #[default]
+    Bt709,
+    /// Full range YCbCr with BT.601 coefficients, as used by JPEG
+    Bt601,
+    /// Full range YCbCr with BT.2020 coefficients
+    Bt2020,
+    /// Reversible integer YCoCg-R transform
+    YCoCgR,
+    /// Luma only, chroma planes are omitted
+    Gray,
+}
+
+impl ColorModel {
+    /// Number of coded planes.
+    pub fn components(self) -> usize {
+        match self {
+            ColorModel::Gray => 1,
+            _ => 3,
+        }
+    }
+
+    fn from_byte(x: u8) -> Result<Self> {
+        match x {
+            0 => Ok(ColorModel::Bt709),
+            1 => Ok(ColorModel::Bt601),
+            2 => Ok(ColorModel::Bt2020),
+            3 => Ok(ColorModel::YCoCgR),
+            4 => Ok(ColorModel::Gray),
+            _ => Err(Error::UnsupportedFeature("color model")),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            ColorModel::Bt709 => 0,
+            ColorModel::Bt601 => 1,
+            ColorModel::Bt2020 => 2,
+            ColorModel::YCoCgR => 3,
+            ColorModel::Gray => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntropyCoder {
+    /// Huffman coded DC differences and AC (run, size) pairs
+    Huffman,
+}
+
+impl EntropyCoder {
+    fn from_byte(x: u8) -> Result<Self> {
+        match x {
+            0 => Ok(EntropyCoder::Huffman),
+            _ => Err(Error::UnsupportedFeature("entropy coder")),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            EntropyCoder::Huffman => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsampling {
+    /// Chroma planes at full resolution
+    S444,
+    /// Chroma planes at half horizontal resolution
+    S422,
+    /// Chroma planes at half horizontal and vertical resolution
+    S420,
+}
+
+impl Subsampling {
+    /// Horizontal and vertical luma samples per chroma sample.
+    pub fn factors(self) -> (usize, usize) {
+        match self {
+            Subsampling::S444 => (1, 1),
+            Subsampling::S422 => (2, 1),
+            Subsampling::S420 => (2, 2),
+        }
+    }
+
+    fn from_byte(x: u8) -> Result<Self> {
+        match x {
+            0 => Ok(Subsampling::S444),
+            1 => Ok(Subsampling::S422),
+            2 => Ok(Subsampling::S420),
+            _ => Err(Error::UnsupportedFeature("subsampling")),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Subsampling::S444 => 0,
+            Subsampling::S422 => 1,
+            Subsampling::S420 => 2,
+        }
+    }
+}
+
+/// Bit depth of the color plane samples entering the DCT.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precision {
+    #[default]
+    Bits8,
+    /// For sources with more than 8 bits per channel, such as `color::Rgb16`
+    Bits12,
+}
+
+impl Precision {
+    pub fn bits(self) -> u32 {
+        match self {
+            Precision::Bits8 => 8,
+            Precision::Bits12 => 12,
+        }
+    }
+
+    /// Largest sample value.
+    pub fn max(self) -> i32 {
+        (1 << self.bits()) - 1
+    }
+}
+
+/// Fixed size header starting every RCR file.
+///
+/// | offset | size | field                 |
+/// |--------|------|-----------------------|
+/// | 0      | 4    | magic bytes `RCR\x1a` |
+/// | 4      | 1    | format version        |
+/// | 5      | 1    | feature flags         |
+/// | 6      | 1    | color model           |
+/// | 7      | 1    | entropy coder         |
+/// | 8      | 1    | chroma subsampling    |
+/// | 9      | 4    | width, big endian     |
+/// | 13     | 4    | height, big endian    |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub flags: u8,
+    pub color_model: ColorModel,
+    pub entropy_coder: EntropyCoder,
+    pub subsampling: Subsampling,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Header {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            version: VERSION,
+            flags: 0,
+            color_model: ColorModel::Bt709,
+            entropy_coder: EntropyCoder::Huffman,
+            subsampling: Subsampling::S444,
+            width,
+            height,
+        }
+    }
+
+    pub fn read<R>(input: &mut R) -> Result<Self>
+    where R: Read
+    {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let mut bytes = [0; 13];
+        input.read_exact(&mut bytes)?;
+
+        let version = bytes[0];
+        if version == 0 || version > VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let flags = bytes[1];
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(Error::UnsupportedFeature("flags"));
+        }
+
+        if flags & FLAG_LOSSLESS_ALPHA != 0 && flags & FLAG_ALPHA == 0 {
+            return Err(Error::InvalidHeader);
+        }
+
+        let width = u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+        let height = u32::from_be_bytes([bytes[9], bytes[10], bytes[11], bytes[12]]);
+
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidDimensions {
+                width: width as usize,
+                height: height as usize,
+            });
+        }
+
+        let header = Self {
+            version,
+            flags,
+            color_model: ColorModel::from_byte(bytes[2])?,
+            entropy_coder: EntropyCoder::from_byte(bytes[3])?,
+            subsampling: Subsampling::from_byte(bytes[4])?,
+            width,
+            height,
+        };
+
+        if flags & FLAG_LOSSLESS != 0 {
+            let model = matches!(header.color_model, ColorModel::YCoCgR | ColorModel::Gray);
+            let alpha = flags & FLAG_ALPHA == 0 || flags & FLAG_LOSSLESS_ALPHA != 0;
+
+            if !model || !alpha || header.subsampling != Subsampling::S444 || flags & FLAG_PROGRESSIVE != 0 {
+                return Err(Error::InvalidHeader);
+            }
+        }
+
+        Ok(header)
+    }
+
+    pub fn precision(&self) -> Precision {
+        match self.flags & FLAG_12_BIT {
+            0 => Precision::Bits8,
+            _ => Precision::Bits12,
+        }
+    }
+
+    pub fn write<W>(&self, output: &mut W) -> Result<()>
+    where W: Write
+    {
+        output.write_all(&MAGIC)?;
+        output.write_all(&[
+            self.version,
+            self.flags,
+            self.color_model.to_byte(),
+            self.entropy_coder.to_byte(),
+            self.subsampling.to_byte(),
+        ])?;
+        output.write_all(&self.width.to_be_bytes())?;
+        output.write_all(&self.height.to_be_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_serialization_equality() {
+        let header = Header::new(1920, 1080);
+        let mut data = Vec::new();
+        header.write(&mut data).unwrap();
+
+        assert_eq!(data.len(), 17);
+        assert_eq!(Header::read(&mut &data[..]).unwrap(), header);
+    }
+
+    #[test]
+    fn read_rejects_unknown_files() {
+        let mut data = Vec::new();
+        Header::new(8, 8).write(&mut data).unwrap();
+
+        let mut invalid = data.clone();
+        invalid[0] = b'X';
+        assert!(matches!(Header::read(&mut &invalid[..]), Err(Error::InvalidMagic)));
+
+        let mut future = data.clone();
+        future[4] = VERSION + 1;
+        assert!(matches!(Header::read(&mut &future[..]), Err(Error::UnsupportedVersion(_))));
+
+        let mut flags = data.clone();
+        flags[5] = 0x80;
+        assert!(matches!(Header::read(&mut &flags[..]), Err(Error::UnsupportedFeature(_))));
+
+        let mut model = data.clone();
+        model[6] = 0xff;
+        assert!(matches!(Header::read(&mut &model[..]), Err(Error::UnsupportedFeature(_))));
+
+        let mut alpha = data.clone();
+        alpha[5] = FLAG_LOSSLESS_ALPHA;
+        assert!(matches!(Header::read(&mut &alpha[..]), Err(Error::InvalidHeader)));
+
+        // Lossless coding needs a reversible color model
+        let mut lossless = data.clone();
+        lossless[5] = FLAG_LOSSLESS;
+        assert!(matches!(Header::read(&mut &lossless[..]), Err(Error::InvalidHeader)));
+        lossless[6] = ColorModel::YCoCgR.to_byte();
+        assert!(Header::read(&mut &lossless[..]).is_ok());
+        lossless[5] |= FLAG_ALPHA;
+        assert!(matches!(Header::read(&mut &lossless[..]), Err(Error::InvalidHeader)));
+
+        let mut coder = data.clone();
+        coder[7] = 0xff;
+        assert!(matches!(Header::read(&mut &coder[..]), Err(Error::UnsupportedFeature(_))));
+    }
+}
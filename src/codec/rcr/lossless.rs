@@ -0,0 +1,93 @@
+use crate::error::{Error, Result};
+
+/// Bound of the magnitude of decoded samples, far beyond valid 12-bit chroma
+/// but low enough that corrupt data cannot overflow predictions.
+const MAX_SAMPLE: i32 = 1 << 16;
+
+/// Median edge detecting predictor of LOCO-I. The first sample is predicted
+/// as `first`, the rest of the first row and column from their only neighbour.
+pub fn predict<T>(samples: &[T], width: usize, i: usize, first: i32) -> i32
+where T: Copy + Into<i32>
+{
+    let (x, y) = (i % width, i / width);
+
+    match (x, y) {
+        (0, 0) => first,
+        (_, 0) => samples[i - 1].into(),
+        (0, _) => samples[i - width].into(),
+        _ => {
+            let a = samples[i - 1].into();
+            let b = samples[i - width].into();
+            let c = samples[i - width - 1].into();
+
+            match c {
+                c if c >= a.max(b) => a.min(b),
+                c if c <= a.min(b) => a.max(b),
+                c => a + b - c,
+            }
+        }
+    }
+}
+
+/// Prediction errors of the centered samples of a plane from `start` on, the
+/// first sample is predicted as zero. Samples before `start` only serve as
+/// context, which lets the plane be coded in bands.
+pub fn residuals(samples: &[i32], width: usize, start: usize) -> Vec<i32> {
+    (start..samples.len())
+        .map(|i| samples[i] - predict(samples, width, i, 0))
+        .collect()
+}
+
+/// Inverse of `residuals`, appending `count` samples to those already in
+/// `samples` with prediction errors pulled from `next`. Fails with
+/// `Error::InvalidData` on samples out of any valid range.
+pub fn reconstruct<F>(samples: &mut Vec<i32>, width: usize, count: usize, mut next: F) -> Result<()>
+where F: FnMut() -> Result<i32>
+{
+    for _ in 0..count {
+        let x = predict(samples, width, samples.len(), 0) + next()?;
+        if x.abs() > MAX_SAMPLE {
+            return Err(Error::InvalidData);
+        }
+
+        samples.push(x);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn residuals_round_trip() {
+        // Ranges of luma and chroma planes of the YCoCg-R transform
+        let (width, height) = (11, 9);
+        let samples: Vec<i32> = (0..width * height)
+            .map(|i| match (i % width, i / width) {
+                (x, y) if x + y < 4 => -128,
+                (x, _) if x > 8 => 255,
+                (x, y) => (x as i32 * 53 - y as i32 * 29) % 256,
+            })
+            .collect();
+
+        let all = residuals(&samples, width, 0);
+
+        let mut iter = all.iter();
+        let mut decoded = Vec::new();
+        reconstruct(&mut decoded, width, width * height, || Ok(*iter.next().unwrap())).unwrap();
+        assert_eq!(decoded, samples);
+
+        // Coding in bands gives the same prediction errors
+        let band = residuals(&samples, width, 4 * width);
+        assert_eq!(band, all[4 * width..]);
+    }
+
+    #[test]
+    fn runaway_samples_are_rejected() {
+        let mut samples = Vec::new();
+        let result = reconstruct(&mut samples, 64, 64, || Ok(1 << 15));
+        assert!(matches!(result, Err(Error::InvalidData)));
+    }
+}
@@ -1,31 +1,166 @@
-use std::io::{BufReader, Read, Result, Write};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::ops::Range;
 
-use crate::color::Lab8;
+use crate::color::Pixel;
+use crate::error::{Error, Result};
 use crate::image::Image;
+use crate::parallel;
 
+use header::Header;
+use entropy::{
+    BitReader, BitWriter, Frequencies, HuffmanTable,
+    decode_difference, decode_scan, encode_block, encode_difference, encode_scan, magnitude_category,
+};
 use unit::Unit;
-use tables::from_quality;
 
+pub use alpha::Alpha;
+pub use header::{ColorModel, Precision, Subsampling};
+pub use rate::{Target, encode_to_target};
+pub use sampling::Upsampling;
+pub use stream::{RcrDecoder, RcrEncoder};
+pub use unit::DctImpl;
+
+pub mod aan;
+pub mod alpha;
+pub mod entropy;
+pub mod header;
+pub mod lossless;
+pub mod rate;
+pub mod sampling;
+pub mod stream;
 pub mod tables;
+pub mod transform;
 pub mod unit;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Settings {
     luma_table: Unit<i32>,
     chroma_table: Unit<i32>,
+    subsampling: Subsampling,
+    color_model: ColorModel,
+    alpha: Alpha,
+    precision: Precision,
+    progressive: bool,
+    dct: DctImpl,
+    lossless: bool,
 }
 
 impl Settings {
     pub fn new() -> Self {
-        Self::quality(5)
+        Self::quality(75).unwrap()
+    }
+
+    /// Settings with quantization tables scaled for a quality from 1 to 100.
+    pub fn quality(quality: usize) -> Result<Self> {
+        let (luma_table, chroma_table) = tables::scaled(quality)?;
+        Self::with_tables(luma_table, chroma_table)
     }
 
-    pub fn quality(quality: usize) -> Self {
-        let (luma_table, chroma_table) = from_quality(quality);
+    /// Settings with custom quantization tables, given in row-major order.
+    pub fn with_tables(luma_table: Unit<i32>, chroma_table: Unit<i32>) -> Result<Self> {
+        tables::validate(&luma_table)?;
+        tables::validate(&chroma_table)?;
 
-        Self {
+        Ok(Self {
             luma_table,
             chroma_table,
+            subsampling: Subsampling::S444,
+            color_model: ColorModel::default(),
+            alpha: Alpha::default(),
+            precision: Precision::default(),
+            progressive: false,
+            dct: DctImpl::default(),
+            lossless: false,
+        })
+    }
+
+    pub fn with_subsampling(mut self, subsampling: Subsampling) -> Self {
+        self.subsampling = subsampling;
+        self
+    }
+
+    pub fn with_color_model(mut self, color_model: ColorModel) -> Self {
+        self.color_model = color_model;
+        self
+    }
+
+    /// Chooses how the alpha plane of images with transparency is coded.
+    pub fn with_alpha(mut self, alpha: Alpha) -> Result<Self> {
+        if let Alpha::Lossy(table) = &alpha {
+            tables::validate(table)?;
         }
+
+        self.alpha = alpha;
+        Ok(self)
+    }
+
+    /// Chooses the bit depth of the color samples, 12 bits keep the precision
+    /// of high depth sources such as `Rgb16`. The alpha plane stays 8-bit.
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Codes the coefficients in several scans of spectral bands, starting
+    /// with the DC coefficients of all blocks, so that a coarse preview can
+    /// be decoded from the beginning of a file with `decode_partial`.
+    pub fn with_progressive(mut self, progressive: bool) -> Self {
+        self.progressive = progressive;
+        self
+    }
+
+    /// Chooses the DCT implementation. `DctImpl::Integer` makes decoding
+    /// bit-exact across platforms, the others are decoded with the fast
+    /// floating point transform.
+    pub fn with_dct(mut self, dct: DctImpl) -> Self {
+        self.dct = dct;
+        self
+    }
+
+    /// Codes the image without any loss. Color planes are converted with
+    /// YCoCg-R at full resolution and predictively coded like lossless alpha,
+    /// so the tables, color model, subsampling, alpha, progressive and DCT
+    /// settings are ignored. 8-bit pixel types are decoded exactly.
+    pub fn with_lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    pub fn luma_table(&self) -> Unit<i32> {
+        self.luma_table
+    }
+
+    pub fn chroma_table(&self) -> Unit<i32> {
+        self.chroma_table
+    }
+
+    pub fn subsampling(&self) -> Subsampling {
+        self.subsampling
+    }
+
+    pub fn color_model(&self) -> ColorModel {
+        self.color_model
+    }
+
+    pub fn alpha(&self) -> Alpha {
+        self.alpha
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn progressive(&self) -> bool {
+        self.progressive
+    }
+
+    pub fn dct(&self) -> DctImpl {
+        self.dct
+    }
+
+    pub fn lossless(&self) -> bool {
+        self.lossless
     }
 }
 
@@ -35,126 +170,1429 @@ impl Default for Settings {
     }
 }
 
-pub fn encode<T>(mut output: T, settings: Settings, image: &Image<Lab8>) -> Result<()>
-where T: Write
-{
-    assert!(image.width() % 8 == 0);
-    assert!(image.height() % 8 == 0);
+/// Kind of a coded plane, deciding its resolution and set of tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Plane {
+    Luma,
+    Chroma,
+    Alpha,
+}
 
-    output.write(&(image.width() as u16).to_be_bytes())?;
-    output.write(&(image.height() as u16).to_be_bytes())?;
+/// Spectral bands of the scans of progressive images, as ranges of zigzag
+/// ordered coefficients.
+const SCANS: [Range<usize>; 4] = [0..1, 1..6, 6..15, 15..64];
 
-    output.write(&settings.luma_table
-        .convert(|x| (x as i8).to_be_bytes()[0])
-        .unwrap()
-    )?;
+/// Single scan of sequential images, covering every coefficient.
+const SEQUENTIAL: Range<usize> = 0..64;
+
+/// Block grid of an image, grouping blocks into minimum coded units (MCU).
+/// Each MCU covers `h`x`v` blocks of the luma and transform coded alpha planes
+/// and a single block of each chroma plane, if the color model has any.
+struct Layout {
+    mcus_x: usize,
+    mcus_y: usize,
+    h: usize,
+    v: usize,
+    planes: Vec<Plane>,
+}
+
+impl Layout {
+    fn new(
+        width: usize,
+        height: usize,
+        subsampling: Subsampling,
+        color_model: ColorModel,
+        alpha: bool,
+    ) -> Self {
+        let (h, v) = subsampling.factors();
+
+        let mut planes = vec![Plane::Luma];
+        if color_model.components() == 3 {
+            planes.extend([Plane::Chroma, Plane::Chroma]);
+        }
+        if alpha {
+            planes.push(Plane::Alpha);
+        }
+
+        Self {
+            mcus_x: width.div_ceil(8 * h),
+            mcus_y: height.div_ceil(8 * v),
+            h,
+            v,
+            planes,
+        }
+    }
 
-    output.write(&settings.chroma_table
-        .convert(|x| (x as i8).to_be_bytes()[0])
+    /// Distinct kinds of planes in coding order, each has its own tables.
+    fn kinds(&self) -> Vec<Plane> {
+        let mut kinds = self.planes.clone();
+        kinds.dedup();
+        kinds
+    }
+
+    /// Index into `kinds` of the tables used by every plane.
+    fn tables(&self) -> Vec<usize> {
+        let kinds = self.kinds();
+        self.planes
+            .iter()
+            .map(|plane| kinds.iter().position(|kind| kind == plane).unwrap_or(0))
+            .collect()
+    }
+
+    /// Dimensions of the part of a plane covered by one row of MCUs.
+    fn band_size(&self, c: usize) -> (usize, usize) {
+        match self.planes[c] {
+            Plane::Chroma => (8 * self.mcus_x, 8),
+            _ => (8 * self.mcus_x * self.h, 8 * self.v),
+        }
+    }
+
+    /// Blocks of one row of MCUs in coding order as `(plane, x, y)`, with
+    /// block coordinates relative to the band. They are produced lazily, as
+    /// decoders only keep the blocks they have read data for.
+    fn band_blocks(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        (0..self.mcus_x).flat_map(move |mx| {
+            self.planes.iter().enumerate().flat_map(move |(c, plane)| {
+                let (h, v) = match plane {
+                    Plane::Chroma => (1, 1),
+                    _ => (self.h, self.v),
+                };
+
+                (0..v).flat_map(move |j| (0..h).map(move |i| (c, mx * h + i, j)))
+            })
+        })
+    }
+}
+
+/// Scales a normalized component to a sample from 0 to `max`.
+fn denormalize(x: f32, max: i32) -> i32 {
+    (x.clamp(0.0, 1.0) * max as f32).round() as i32
+}
+
+/// Alpha samples of a row of normalized pixels.
+fn alpha_row(row: &[[f32; 4]]) -> Vec<u8> {
+    row.iter().map(|p| denormalize(p[3], u8::MAX as i32) as u8).collect()
+}
+
+fn forward_unit(block: [i32; 64], dct: DctImpl) -> Unit<f32> {
+    match dct {
+        DctImpl::Float => Unit::new(block).convert(|x| x as f32).dct(),
+        DctImpl::Integer => Unit::new(block).dct().convert(|x| x as f32),
+        DctImpl::Fast => Unit::new(block).convert(|x| x as f32).fast_dct(),
+    }
+}
+
+fn quantize_unit(coefficients: Unit<f32>, table: Unit<i32>) -> [i32; 64] {
+    coefficients
+        .quantize(table.convert(|x| x as f32))
+        .convert(|x| x as i32)
+        .zigzag()
         .unwrap()
-    )?;
-
-    image
-        .iter_block()
-        .for_each(|block| {
-            let mut l = [0; 64];
-            let mut a = [0; 64];
-            let mut b = [0; 64];
-
-            for i in 0..64 {
-                l[i] = block[i].l;
-                a[i] = block[i].a;
-                b[i] = block[i].b;
+}
+
+fn decode_unit(coefficients: [i32; 64], table: Unit<i32>, dct: DctImpl) -> Result<[i32; 64]> {
+    let coefficients = Unit::new(coefficients)
+        .inv_zigzag()
+        .checked_inv_quantize(table)?;
+
+    Ok(match dct {
+        DctImpl::Integer => coefficients.inv_dct().unwrap(),
+        _ => coefficients
+            .convert(|x| x as f32)
+            .fast_inv_dct()
+            .convert(|x| x.round() as i32)
+            .unwrap(),
+    })
+}
+
+/// Dimensions and coding choices shared by all bands of an image. A band is
+/// one row of MCUs, the unit in which images are encoded and decoded.
+struct Frame {
+    width: usize,
+    height: usize,
+    subsampling: Subsampling,
+    color_model: ColorModel,
+    precision: Precision,
+    dct: DctImpl,
+    /// Coding of the alpha plane, if the image has one
+    alpha: Option<Alpha>,
+    /// Color planes are predictively coded instead of split into blocks
+    lossless: bool,
+    layout: Layout,
+    /// Spectral bands of the scans, a single one for sequential images
+    scans: Vec<Range<usize>>,
+}
+
+impl Frame {
+    fn new<P>(width: usize, height: usize, settings: &Settings) -> Result<Self>
+    where P: Pixel
+    {
+        if width == 0 || height == 0 || width > u32::MAX as usize || height > u32::MAX as usize {
+            return Err(Error::InvalidDimensions { width, height });
+        }
+
+        // Single channel pixels are always coded without chroma planes
+        let lossless = settings.lossless;
+        let color_model = match (P::CHANNELS, lossless) {
+            (1, _) => ColorModel::Gray,
+            (_, true) => ColorModel::YCoCgR,
+            _ => settings.color_model,
+        };
+        let subsampling = match (color_model, lossless) {
+            (ColorModel::Gray, _) | (_, true) => Subsampling::S444,
+            _ => settings.subsampling,
+        };
+        let alpha = match (P::CHANNELS, lossless) {
+            (4, true) => Some(Alpha::Lossless),
+            (4, false) => Some(settings.alpha),
+            _ => None,
+        };
+
+        let lossy_alpha = matches!(alpha, Some(Alpha::Lossy(_)));
+
+        Ok(Self {
+            width,
+            height,
+            subsampling,
+            color_model,
+            precision: settings.precision,
+            dct: settings.dct,
+            alpha,
+            lossless,
+            layout: Layout::new(width, height, subsampling, color_model, lossy_alpha),
+            scans: match settings.progressive && !lossless {
+                true => SCANS.to_vec(),
+                false => vec![SEQUENTIAL],
+            },
+        })
+    }
+
+    fn progressive(&self) -> bool {
+        self.scans.len() > 1
+    }
+
+    /// Number of pixel rows of every band but the last.
+    fn band_height(&self) -> usize {
+        8 * self.layout.v
+    }
+
+    fn bands(&self) -> usize {
+        self.layout.mcus_y
+    }
+
+    /// Pixel rows covered by band `m`.
+    fn band_rows(&self, m: usize) -> Range<usize> {
+        let start = m * self.band_height();
+        start..(start + self.band_height()).min(self.height)
+    }
+
+    /// Blocks of one band in coding order, none in lossless mode.
+    fn band_blocks(&self) -> Vec<(usize, usize, usize)> {
+        match self.lossless {
+            true => Vec::new(),
+            false => self.layout.band_blocks().collect(),
+        }
+    }
+
+    /// Quantization tables of every kind of plane. They come from the
+    /// settings, except for lossy alpha which carries its own. Lossless
+    /// images have none.
+    fn quantization(&self, settings: &Settings) -> Vec<Unit<i32>> {
+        if self.lossless {
+            return Vec::new();
+        }
+
+        self.layout
+            .kinds()
+            .iter()
+            .map(|kind| match (kind, self.alpha) {
+                (Plane::Chroma, _) => settings.chroma_table,
+                (Plane::Alpha, Some(Alpha::Lossy(table))) => table,
+                _ => settings.luma_table,
+            })
+            .collect()
+    }
+
+    /// Transforms the normalized pixels of one band into DCT blocks. `above`
+    /// holds the row of pixels before the band, the context of predictive
+    /// coding, and is empty for the first band.
+    fn analyze(&self, pixels: &Image<[f32; 4]>, above: &[[f32; 4]]) -> Band {
+        let (color_model, precision) = (self.color_model, self.precision);
+        let color = |&[r, g, b, _]: &[f32; 4]| {
+            let rgb = [r, g, b].map(|x| denormalize(x, precision.max()));
+            transform::forward(color_model, precision, rgb)
+        };
+        let samples = pixels.map(color);
+        let alpha_samples = || pixels.map(|p| denormalize(p[3], u8::MAX as i32));
+
+        let (h, v) = self.subsampling.factors();
+        let plane = |c: usize| samples.map(|x| x[c]);
+
+        // Edges of the last band are replicated like those of the whole image
+        let planes: Vec<Image<i32>> = self.layout.planes
+            .iter()
+            .enumerate()
+            .map(|(c, kind)| match kind {
+                Plane::Luma => plane(0),
+                Plane::Chroma => sampling::downsample(&plane(c), h, v),
+                Plane::Alpha => alpha_samples().map(|&x| x - 128),
+            })
+            .collect();
+
+        let blocks = parallel::map(&self.band_blocks(), |&(c, x, y)| {
+            (c, forward_unit(planes[c].block(x, y), self.dct))
+        });
+
+        // Lossless color planes are followed by lossless alpha
+        let mut residuals = Vec::new();
+        if self.lossless {
+            let context: Vec<[i32; 3]> = above.iter().map(color).collect();
+            for (c, plane) in planes.iter().enumerate() {
+                let mut samples: Vec<i32> = context.iter().map(|x| x[c]).collect();
+                samples.extend_from_slice(plane.data());
+                residuals.extend(lossless::residuals(&samples, self.width, above.len()));
             }
+        }
+        if self.alpha == Some(Alpha::Lossless) {
+            let mut samples = alpha_row(above);
+            samples.extend(alpha_samples().data().iter().map(|&x| x as u8));
+            residuals.extend(alpha::residuals(&samples, self.width, above.len()));
+        }
+
+        Band { blocks, residuals }
+    }
+
+    /// Quantizes the blocks of a band with the given tables of every kind.
+    fn quantize(&self, band: &Band, quantization: &[Unit<i32>]) -> Vec<(usize, [i32; 64])> {
+        let slots = self.layout.tables();
+        band.blocks
+            .iter()
+            .map(|&(c, coefficients)| (c, quantize_unit(coefficients, quantization[slots[c]])))
+            .collect()
+    }
+}
 
-            let mut write_helper = |x: [i8; 64], t: Unit<i32>| -> Result<_> {
-                output.write(&Unit::new(x)
-                    .convert(|x| x as f32)
-                    .dct()
-                    .convert(|x| x as i32)
-                    .quantize(t)
-                    .convert(|x| i8::to_be_bytes(x as i8)[0])
-                    .zigzag()
-                    .unwrap()
-                )
+/// DCT coefficients of the blocks of one band in coding order, followed by
+/// the prediction errors of losslessly coded planes.
+struct Band {
+    blocks: Vec<(usize, Unit<f32>)>,
+    residuals: Vec<i32>,
+}
+
+/// Coefficients of every band of an image. Computing them does not depend on
+/// the quantization tables, so they can be shared between trial encodes with
+/// different settings.
+struct Spectrum {
+    frame: Frame,
+    bands: Vec<Band>,
+}
+
+impl Spectrum {
+    fn new<P>(image: &Image<P>, settings: &Settings) -> Result<Self>
+    where P: Pixel
+    {
+        let frame = Frame::new::<P>(image.width(), image.height(), settings)?;
+        let pixels = image.map(|&p| p.to_normalized());
+        let width = frame.width;
+
+        // Bands only depend on the row above them, so they are independent
+        let bands = parallel::map_range(0..frame.bands(), |m| {
+            let rows = frame.band_rows(m);
+            let data = pixels.data()[rows.start * width..rows.end * width].to_vec();
+            let above = match rows.start {
+                0 => &[][..],
+                y => &pixels.data()[(y - 1) * width..y * width],
             };
 
-            // TODO: proper error handler
-            write_helper(l, settings.luma_table).unwrap();
-            write_helper(a, settings.chroma_table).unwrap();
-            write_helper(b, settings.chroma_table).unwrap();
+            frame.analyze(&Image::new(width, rows.len(), data), above)
         });
 
+        Ok(Self { frame, bands })
+    }
+}
+
+/// Quantization and Huffman tables of every kind of plane, stored after the
+/// header.
+struct Tables {
+    quantization: Vec<Unit<i32>>,
+    huffman: Vec<(HuffmanTable, HuffmanTable)>,
+    /// Table of the prediction errors of lossless planes
+    residual: Option<HuffmanTable>,
+}
+
+impl Tables {
+    /// Builds optimal Huffman tables for the given quantized bands and their
+    /// prediction errors. With `complete`, the bands are taken as a
+    /// sample of the image and every symbol is given a code, so that bands
+    /// that were not seen can be coded as well.
+    fn new<'a, I>(frame: &Frame, quantization: Vec<Unit<i32>>, bands: I, complete: bool) -> Self
+    where I: IntoIterator<Item = (&'a [(usize, [i32; 64])], &'a [i32])>
+    {
+        let slots = frame.layout.tables();
+        let mut frequencies: Vec<Frequencies> = quantization.iter().map(|_| Frequencies::new()).collect();
+        let mut counts = [0; 256];
+        let mut prev_dc = vec![0; frame.layout.planes.len()];
+
+        for (blocks, residuals) in bands {
+            for &(c, ref block) in blocks {
+                for scan in &frame.scans {
+                    frequencies[slots[c]].count_scan(block, prev_dc[c], scan.clone());
+                }
+                prev_dc[c] = block[0];
+            }
+
+            for &r in residuals {
+                counts[magnitude_category(r) as usize] += 1;
+            }
+        }
+
+        if complete {
+            // Weighted so that rare symbols do not take much of the code space
+            let weight = |n: &mut u32| *n = n.saturating_mul(frame.bands() as u32).saturating_add(1);
+            for f in &mut frequencies {
+                f.dc.iter_mut().chain(&mut f.ac).for_each(weight);
+            }
+            counts.iter_mut().for_each(weight);
+        }
+
+        let huffman = frequencies
+            .iter()
+            .map(|f| (HuffmanTable::from_frequencies(&f.dc), HuffmanTable::from_frequencies(&f.ac)))
+            .collect();
+
+        let residual = match frame.lossless || frame.alpha == Some(Alpha::Lossless) {
+            true => Some(HuffmanTable::from_frequencies(&counts)),
+            false => None,
+        };
+
+        Self {
+            quantization,
+            huffman,
+            residual,
+        }
+    }
+}
+
+/// Writes the header followed by all tables.
+fn write_head<T>(output: &mut T, frame: &Frame, tables: &Tables) -> Result<()>
+where T: Write
+{
+    let wide = tables.quantization.iter().any(tables::is_wide);
+
+    let mut header = Header::new(frame.width as u32, frame.height as u32);
+    header.subsampling = frame.subsampling;
+    header.color_model = frame.color_model;
+    if frame.precision == Precision::Bits12 {
+        header.flags |= header::FLAG_12_BIT;
+    }
+    if wide {
+        header.flags |= header::FLAG_WIDE_TABLES;
+    }
+    match frame.alpha {
+        Some(Alpha::Lossless) => header.flags |= header::FLAG_ALPHA | header::FLAG_LOSSLESS_ALPHA,
+        Some(Alpha::Lossy(_)) => header.flags |= header::FLAG_ALPHA,
+        None => {}
+    }
+    if frame.progressive() {
+        header.flags |= header::FLAG_PROGRESSIVE;
+    }
+    if frame.dct == DctImpl::Integer {
+        header.flags |= header::FLAG_INTEGER_DCT;
+    }
+    if frame.lossless {
+        header.flags |= header::FLAG_LOSSLESS;
+    }
+    header.write(output)?;
+
+    for table in &tables.quantization {
+        tables::write(output, table, wide)?;
+    }
+
+    for (dc, ac) in &tables.huffman {
+        dc.write(output)?;
+        ac.write(output)?;
+    }
+
+    if let Some(table) = &tables.residual {
+        table.write(output)?;
+    }
+
+    // Scans are listed by the end of their spectral band
+    if frame.progressive() {
+        output.write_all(&[frame.scans.len() as u8])?;
+        output.write_all(&frame.scans.iter().map(|scan| scan.end as u8).collect::<Vec<_>>())?;
+    }
+
     Ok(())
 }
 
-pub fn decode<T>(input: T) -> Result<Image<Lab8>>
+/// Inverse of `write_head`.
+fn read_head<T>(input: &mut T) -> Result<(Frame, Tables)>
 where T: Read
 {
-    let mut input = BufReader::new(input);
-    let mut bytes = [0; 2];
-
-    input.read_exact(&mut bytes)?;
-    let width: u16 = u16::from_be_bytes(bytes);
-
-    input.read_exact(&mut bytes)?;
-    let height: u16 = u16::from_be_bytes(bytes);
-    
-    // TODO: proper error handler
-    assert!(width % 8 == 0);
-    assert!(height % 8 == 0);
-
-    let mut bytes = [0; 64];
-    input.read_exact(&mut bytes)?;
-    let luma_table = Unit::new(bytes)
-        .convert(|x| i8::from_be_bytes([x]) as i32);
-
-    let mut bytes = [0; 64];
-    input.read_exact(&mut bytes)?;
-    let chroma_table = Unit::new(bytes)
-        .convert(|x| i8::from_be_bytes([x]) as i32);
-
-    let w = (width / 8) as usize;
-    let h = (height / 8) as usize;
-
-    let mut data: Vec<Lab8> = vec![Lab8::default(); width as usize * height as usize];
-    
-    for y in 0..h {
-        for x in 0..w {
-            let mut read_helper = |t: Unit<i32>| -> Result<[i8; 64]> {
-                let mut raw = [0; 64];
-                input.read_exact(&mut raw)?;
-
-                let data = Unit::new(raw)
-                    .inv_zigzag()
-                    .convert(|x| i8::from_be_bytes([x]) as i32)
-                    .inv_quantize(t)
-                    .convert(|x| x as f32)
-                    .inv_dct()
-                    .convert(|x| x as i8)
-                    .unwrap();
-
-                Ok(data)
-            };
+    let header = Header::read(input)?;
+
+    let width = header.width as usize;
+    let height = header.height as usize;
+
+    let wide = header.flags & header::FLAG_WIDE_TABLES != 0;
+    let alpha = header.flags & header::FLAG_ALPHA != 0;
+    let lossless_alpha = header.flags & header::FLAG_LOSSLESS_ALPHA != 0;
+    let lossless = header.flags & header::FLAG_LOSSLESS != 0;
+
+    let layout = Layout::new(
+        width,
+        height,
+        header.subsampling,
+        header.color_model,
+        alpha && !lossless_alpha,
+    );
+    let kinds = match lossless {
+        true => Vec::new(),
+        false => layout.kinds(),
+    };
+
+    let quantization: Vec<Unit<i32>> = kinds
+        .iter()
+        .map(|_| tables::read(input, wide))
+        .collect::<Result<_>>()?;
+
+    let huffman = kinds
+        .iter()
+        .map(|_| Ok((HuffmanTable::read(input)?, HuffmanTable::read(input)?)))
+        .collect::<Result<_>>()?;
+
+    let residual = match lossless || lossless_alpha {
+        true => Some(HuffmanTable::read(input)?),
+        false => None,
+    };
+
+    let scans = match header.flags & header::FLAG_PROGRESSIVE != 0 {
+        true => read_scan_script(input)?,
+        false => vec![SEQUENTIAL],
+    };
+
+    let alpha = match (alpha, lossless_alpha) {
+        (true, true) => Some(Alpha::Lossless),
+        (true, false) => Some(Alpha::Lossy(quantization[kinds.len() - 1])),
+        _ => None,
+    };
+
+    let frame = Frame {
+        width,
+        height,
+        subsampling: header.subsampling,
+        color_model: header.color_model,
+        precision: header.precision(),
+        dct: match header.flags & header::FLAG_INTEGER_DCT != 0 {
+            true => DctImpl::Integer,
+            false => DctImpl::Fast,
+        },
+        alpha,
+        lossless,
+        layout,
+        scans,
+    };
+
+    Ok((frame, Tables { quantization, huffman, residual }))
+}
+
+fn read_scan_script<T>(input: &mut T) -> Result<Vec<Range<usize>>>
+where T: Read
+{
+    let mut count = [0; 1];
+    input.read_exact(&mut count)?;
+
+    let mut ends = vec![0; count[0] as usize];
+    input.read_exact(&mut ends)?;
+
+    let mut scans = Vec::new();
+    let mut start = 0;
+    for end in ends.into_iter().map(usize::from) {
+        if end <= start || end > 64 {
+            return Err(Error::InvalidData);
+        }
+
+        scans.push(start..end);
+        start = end;
+    }
+
+    // Scans have to cover every coefficient, in more than one step
+    if start != 64 || scans.len() < 2 {
+        return Err(Error::InvalidData);
+    }
+
+    Ok(scans)
+}
+
+/// Codes the quantized blocks of one band followed by its prediction errors.
+/// `prev_dc` carries the DC predictions of every plane across bands.
+fn write_band(
+    writer: &mut BitWriter,
+    frame: &Frame,
+    tables: &Tables,
+    blocks: &[(usize, [i32; 64])],
+    residuals: &[i32],
+    prev_dc: &mut [i32],
+) {
+    let slots = frame.layout.tables();
+
+    for &(c, ref block) in blocks {
+        let (dc, ac) = &tables.huffman[slots[c]];
+        encode_block(writer, block, prev_dc[c], dc, ac);
+        prev_dc[c] = block[0];
+    }
+
+    if let Some(table) = &tables.residual {
+        for &r in residuals {
+            encode_difference(writer, r, table);
+        }
+    }
+}
+
+/// Encodes an image of any pixel type, converting it into planes with the
+/// color model chosen in `settings`. Single channel images such as `Gray8`
+/// always use `ColorModel::Gray`, the alpha channel of `Rgba8` is coded as
+/// chosen by `Settings::with_alpha`.
+///
+/// The whole image is held in memory, see `RcrEncoder` for large images.
+pub fn encode<T, P>(output: T, settings: Settings, image: &Image<P>) -> Result<()>
+where
+    T: Write,
+    P: Pixel,
+{
+    let spectrum = Spectrum::new(image, &settings)?;
+    write_spectrum(output, &settings, &spectrum)
+}
+
+fn write_spectrum<T>(mut output: T, settings: &Settings, spectrum: &Spectrum) -> Result<()>
+where T: Write
+{
+    let frame = &spectrum.frame;
+    let quantization = frame.quantization(settings);
+
+    let blocks = parallel::map(&spectrum.bands, |band| frame.quantize(band, &quantization));
+
+    // Huffman tables are fit to all bands of the image
+    let bands = || blocks
+        .iter()
+        .zip(&spectrum.bands)
+        .map(|(blocks, band)| (&blocks[..], &band.residuals[..]));
+    let tables = Tables::new(frame, quantization, bands(), false);
+
+    write_head(&mut output, frame, &tables)?;
+
+    let mut writer = BitWriter::new();
+
+    match frame.progressive() {
+        true => write_scans(&mut writer, frame, &tables, &blocks, &spectrum.bands),
+        false => {
+            let mut prev_dc = vec![0; frame.layout.planes.len()];
+            for (blocks, residuals) in bands() {
+                write_band(&mut writer, frame, &tables, blocks, residuals, &mut prev_dc);
+            }
+        }
+    }
+
+    output.write_all(&writer.into_bytes())?;
+
+    Ok(())
+}
 
-            let l = read_helper(luma_table)?;
-            let a = read_helper(chroma_table)?;
-            let b = read_helper(chroma_table)?;
+/// Codes the quantized blocks of all bands scan by scan, each scan starting
+/// on a byte boundary. Lossless alpha follows the first scan, so previews
+/// already have their final transparency.
+fn write_scans(
+    writer: &mut BitWriter,
+    frame: &Frame,
+    tables: &Tables,
+    blocks: &[Vec<(usize, [i32; 64])>],
+    bands: &[Band],
+) {
+    let slots = frame.layout.tables();
 
-            for j in 0..8 {
-                for i in 0..8 {
-                    let index = i + (8 * x) + (8 * y + j) * width as usize;
-                    data[index].l = l[i + 8 * j];
-                    data[index].a = a[i + 8 * j];
-                    data[index].b = b[i + 8 * j];
+    for (i, scan) in frame.scans.iter().enumerate() {
+        let mut prev_dc = vec![0; frame.layout.planes.len()];
+
+        for blocks in blocks {
+            for &(c, ref block) in blocks {
+                let (dc, ac) = &tables.huffman[slots[c]];
+                encode_scan(writer, block, prev_dc[c], scan.clone(), dc, ac);
+                prev_dc[c] = block[0];
+            }
+        }
+
+        if let (0, Some(table)) = (i, &tables.residual) {
+            for band in bands {
+                for &r in &band.residuals {
+                    encode_difference(writer, r, table);
+                }
+            }
+        }
+
+        writer.pad();
+    }
+}
+
+/// Quantized coefficients of every band of a progressive image, which have to
+/// be read in full before any band can be reconstructed.
+struct Scanned {
+    bands: VecDeque<Vec<[i32; 64]>>,
+    /// Samples of a losslessly coded alpha plane
+    alpha: Option<Vec<u8>>,
+    /// Number of scans read completely
+    scans: usize,
+}
+
+/// Reads the scans of a progressive image. With `partial`, data ending after
+/// the first scan yields the coefficients read so far instead of an error.
+fn read_scans<R>(reader: &mut BitReader<R>, frame: &Frame, tables: &Tables, partial: bool) -> Result<Scanned>
+where R: Read
+{
+    let layout = &frame.layout;
+    let slots = layout.tables();
+
+    let mut scanned = Scanned {
+        bands: VecDeque::new(),
+        alpha: None,
+        scans: 0,
+    };
+
+    for (i, scan) in frame.scans.iter().enumerate() {
+        let mut read = || -> Result<()> {
+            let mut prev_dc = vec![0; layout.planes.len()];
+
+            for m in 0..frame.bands() {
+                // The first scan allocates blocks as it reads them, so that
+                // dimensions in the header cannot exhaust memory without data
+                if i == 0 {
+                    scanned.bands.push_back(Vec::new());
+                }
+
+                for (k, (c, _, _)) in layout.band_blocks().enumerate() {
+                    if i == 0 {
+                        scanned.bands[m].push([0; 64]);
+                    }
+
+                    let block = &mut scanned.bands[m][k];
+                    let (dc, ac) = &tables.huffman[slots[c]];
+                    decode_scan(reader, block, prev_dc[c], scan.clone(), dc, ac)?;
+                    prev_dc[c] = block[0];
                 }
             }
+
+            if let (0, Some(table)) = (i, &tables.residual) {
+                let mut samples = Vec::new();
+                let count = frame.width * frame.height;
+                alpha::reconstruct(&mut samples, frame.width, count, || decode_difference(reader, table))?;
+                scanned.alpha = Some(samples);
+            }
+
+            reader.align();
+            Ok(())
+        };
+
+        match read() {
+            Ok(()) => scanned.scans += 1,
+            Err(Error::Truncated) if partial && scanned.scans > 0 => break,
+            Err(err) => return Err(err),
         }
     }
-    
+
+    Ok(scanned)
+}
+
+/// Number of scans decoded out of those in a file. Sequential images consist
+/// of a single scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub scans: usize,
+    pub total: usize,
+}
+
+impl Progress {
+    pub fn is_complete(&self) -> bool {
+        self.scans == self.total
+    }
+}
+
+/// Decodes an image into any pixel type, e.g. `decode::<_, Rgb8>(input)`.
+/// Pixel types without alpha drop the alpha plane.
+pub fn decode<T, P>(input: T) -> Result<Image<P>>
+where
+    T: Read,
+    P: Pixel,
+{
+    decode_with(input, Upsampling::default())
+}
+
+/// Decodes an image, scaling subsampled chroma planes with the given filter.
+pub fn decode_with<T, P>(input: T, upsampling: Upsampling) -> Result<Image<P>>
+where
+    T: Read,
+    P: Pixel,
+{
+    read_image(RcrDecoder::with_upsampling(input, upsampling)?)
+}
+
+/// Decodes as much of a progressive image as `input` holds, giving a preview
+/// with the coefficients of the scans read so far along with the number of
+/// complete scans. At least the first scan has to be complete, sequential
+/// images have to be complete.
+pub fn decode_partial<T, P>(input: T) -> Result<(Image<P>, Progress)>
+where
+    T: Read,
+    P: Pixel,
+{
+    let decoder = RcrDecoder::partial(input)?;
+    let progress = decoder.progress();
+
+    Ok((read_image(decoder)?, progress))
+}
+
+fn read_image<T, P>(mut decoder: RcrDecoder<T, P>) -> Result<Image<P>>
+where
+    T: Read,
+    P: Pixel,
+{
+    let (width, height) = (decoder.width(), decoder.height());
+
+    // Dimensions come from the header, so the image grows as bands are
+    // decoded instead of being allocated up front
+    width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(size_of::<P>()))
+        .filter(|&n| n <= isize::MAX as usize)
+        .ok_or(Error::InvalidDimensions { width, height })?;
+
+    let mut data = Vec::new();
+    while let Some(band) = decoder.read_band()? {
+        data.extend_from_slice(band.data());
+    }
+
     Ok(Image::new(
-        width as usize,
-        height as usize,
+        width,
+        height,
         data
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ppm;
+    use crate::color::{Gray8, Lab8, Rgb16, Rgb8, Rgba8};
+    use crate::metrics;
+
+    #[test]
+    fn encode_rejects_empty_image() {
+        let image: Image<Lab8> = Image::new(0, 8, Vec::new());
+        let result = encode(Vec::new(), Settings::default(), &image);
+
+        assert!(matches!(result, Err(Error::InvalidDimensions { width: 0, height: 8 })));
+    }
+
+    #[test]
+    fn unaligned_dimensions_round_trip() {
+        for (width, height) in [(1, 1), (13, 8), (8, 21), (17, 30)] {
+            let data = (0..width * height)
+                .map(|i| (i % width, i / width))
+                .map(|(x, y)| Lab8 { l: (2 * x + 3 * y) as i8 - 40, a: -10, b: 20 })
+                .collect();
+            let image = Image::new(width, height, data);
+
+            let mut data = Vec::new();
+            encode(&mut data, Settings::quality(50).unwrap(), &image).unwrap();
+            let decoded: Image<Lab8> = decode(&data[..]).unwrap();
+
+            assert_eq!(decoded.width(), width);
+            assert_eq!(decoded.height(), height);
+            image
+                .data()
+                .iter()
+                .zip(decoded.data())
+                .for_each(|(a, b)| assert!((a.l as i32 - b.l as i32).abs() < 16));
+        }
+    }
+
+    #[test]
+    fn file_size_follows_quality() {
+        let image: Image<Lab8> = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap()
+            .into();
+
+        let size = |quality| {
+            let mut data = Vec::new();
+            encode(&mut data, Settings::quality(quality).unwrap(), &image).unwrap();
+            data.len()
+        };
+
+        // One byte per coefficient would take 192 * 192 * 3 bytes
+        assert!(size(75) < 192 * 192 * 3 / 8);
+        assert!(size(10) < size(50));
+        assert!(size(50) < size(75));
+    }
+
+    #[test]
+    fn subsampling_round_trip() {
+        let data = (0..27 * 19)
+            .map(|i| (i % 27, i / 27))
+            .map(|(x, y)| Lab8 { l: (2 * x + 3 * y) as i8 - 40, a: x as i8 - 10, b: 20 - y as i8 })
+            .collect();
+        let image = Image::new(27, 19, data);
+
+        for subsampling in [Subsampling::S444, Subsampling::S422, Subsampling::S420] {
+            let mut data = Vec::new();
+            let settings = Settings::quality(50).unwrap().with_subsampling(subsampling);
+            encode(&mut data, settings, &image).unwrap();
+
+            for upsampling in [Upsampling::Nearest, Upsampling::Bilinear] {
+                let decoded: Image<Lab8> = decode_with(&data[..], upsampling).unwrap();
+
+                assert_eq!(decoded.width(), 27);
+                assert_eq!(decoded.height(), 19);
+                image
+                    .data()
+                    .iter()
+                    .zip(decoded.data())
+                    .for_each(|(p, q)| {
+                        assert!((p.l as i32 - q.l as i32).abs() < 16);
+                        assert!((p.a as i32 - q.a as i32).abs() < 16);
+                        assert!((p.b as i32 - q.b as i32).abs() < 16);
+                    });
+            }
+        }
+    }
+
+    #[test]
+    fn subsampling_reduces_file_size() {
+        let image: Image<Lab8> = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap()
+            .into();
+
+        let size = |subsampling| {
+            let mut data = Vec::new();
+            encode(&mut data, Settings::new().with_subsampling(subsampling), &image).unwrap();
+            data.len()
+        };
+
+        assert!(size(Subsampling::S422) < size(Subsampling::S444));
+        assert!(size(Subsampling::S420) < size(Subsampling::S422));
+    }
+
+    #[test]
+    fn fine_quantizers_do_not_clip() {
+        let image: Image<Lab8> = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap()
+            .into();
+
+        let error = |settings| {
+            let mut data = Vec::new();
+            encode(&mut data, settings, &image).unwrap();
+            let decoded: Image<Lab8> = decode(&data[..]).unwrap();
+
+            image
+                .data()
+                .iter()
+                .zip(decoded.data())
+                .map(|(p, q)| (p.l as i32 - q.l as i32).abs())
+                .max()
+                .unwrap()
+        };
+
+        let (luma, chroma) = tables::from_quality(12).unwrap();
+        assert!(error(Settings::with_tables(luma, chroma).unwrap()) <= 4);
+        assert!(error(Settings::quality(100).unwrap()) <= 4);
+    }
+
+    #[test]
+    fn reconstruction_error_is_small_and_unbiased() {
+        let image = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..]).unwrap();
+
+        // Mean squared and mean signed error over all RGB samples
+        let error = |quality| {
+            let mut data = Vec::new();
+            encode(&mut data, Settings::quality(quality).unwrap(), &image).unwrap();
+            let decoded: Image<Rgb8> = decode(&data[..]).unwrap();
+
+            let (mut squared, mut signed) = (0.0, 0.0);
+            for (p, q) in image.data().iter().zip(decoded.data()) {
+                for (x, y) in [(p.r, q.r), (p.g, q.g), (p.b, q.b)] {
+                    let d = x as f64 - y as f64;
+                    squared += d * d;
+                    signed += d;
+                }
+            }
+
+            let n = (image.data().len() * 3) as f64;
+            (squared / n, signed / n)
+        };
+
+        // Truncating quantization and conversions measured 18.96 / 0.47 and 4.80 / 0.47
+        let (mse, bias) = error(90);
+        assert!(mse < 10.0);
+        assert!(bias.abs() < 0.1);
+
+        let (mse, bias) = error(100);
+        assert!(mse < 1.0);
+        assert!(bias.abs() < 0.1);
+    }
+
+    #[test]
+    fn color_models_round_trip() {
+        let image = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..]).unwrap();
+        let models = [
+            ColorModel::Bt601,
+            ColorModel::Bt709,
+            ColorModel::Bt2020,
+            ColorModel::YCoCgR,
+        ];
+
+        for model in models {
+            let settings = Settings::quality(100).unwrap().with_color_model(model);
+            let mut data = Vec::new();
+            encode(&mut data, settings, &image).unwrap();
+
+            let header = Header::read(&mut &data[..]).unwrap();
+            assert_eq!(header.color_model, model);
+
+            let decoded: Image<Rgb8> = decode(&data[..]).unwrap();
+            let max = image
+                .data()
+                .iter()
+                .zip(decoded.data())
+                .flat_map(|(p, q)| [(p.r, q.r), (p.g, q.g), (p.b, q.b)])
+                .map(|(x, y)| (x as i32 - y as i32).abs())
+                .max()
+                .unwrap();
+
+            assert!(max <= 4, "{:?} {}", model, max);
+        }
+    }
+
+    #[test]
+    fn grayscale_skips_chroma_planes() {
+        let image: Image<Gray8> = ppm::decode_gray(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap();
+        let rgb: Image<Rgb8> = image.convert();
+
+        let mut gray = Vec::new();
+        encode(&mut gray, Settings::new(), &image).unwrap();
+        assert_eq!(Header::read(&mut &gray[..]).unwrap().color_model, ColorModel::Gray);
+
+        let mut color = Vec::new();
+        encode(&mut color, Settings::new(), &rgb).unwrap();
+        assert!(gray.len() < color.len());
+
+        let mut explicit = Vec::new();
+        encode(&mut explicit, Settings::new().with_color_model(ColorModel::Gray), &rgb).unwrap();
+        assert_eq!(explicit, gray);
+
+        let decoded: Image<Gray8> = decode(&gray[..]).unwrap();
+        let max = image
+            .data()
+            .iter()
+            .zip(decoded.data())
+            .map(|(p, q)| (p.v as i32 - q.v as i32).abs())
+            .max()
+            .unwrap();
+        assert!(max < 32);
+    }
+
+    fn transparent_sample() -> Image<Rgba8> {
+        let image = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..]).unwrap();
+        let data = image
+            .data()
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let (x, y) = ((i % 192) as i32 - 96, (i / 192) as i32 - 96);
+                let a = match x * x + y * y {
+                    d if d < 40 * 40 => 255,
+                    d if d < 80 * 80 => (255 - (d - 1600) / 19).clamp(0, 255) as u8,
+                    _ => 0,
+                };
+
+                Rgba8 { a, ..p.into() }
+            })
+            .collect();
+
+        Image::new(192, 192, data)
+    }
+
+    #[test]
+    fn lossless_alpha_round_trip() {
+        let image = transparent_sample();
+
+        let mut data = Vec::new();
+        encode(&mut data, Settings::new(), &image).unwrap();
+
+        let header = Header::read(&mut &data[..]).unwrap();
+        assert_eq!(header.flags & header::FLAG_ALPHA, header::FLAG_ALPHA);
+
+        let decoded: Image<Rgba8> = decode(&data[..]).unwrap();
+        assert!(image.data().iter().zip(decoded.data()).all(|(p, q)| p.a == q.a));
+
+        // Color planes are unaffected by the alpha plane
+        let opaque: Image<Rgb8> = image.convert();
+        let mut color = Vec::new();
+        encode(&mut color, Settings::new(), &opaque).unwrap();
+        let color: Image<Rgb8> = decode(&color[..]).unwrap();
+        assert_eq!(decoded.convert::<Rgb8>().data(), color.data());
+
+        let without_alpha: Image<Rgb8> = decode(&data[..]).unwrap();
+        assert_eq!(without_alpha.data(), color.data());
+    }
+
+    #[test]
+    fn lossy_alpha_round_trip() {
+        let image = transparent_sample();
+        let (table, _) = tables::scaled(90).unwrap();
+
+        let mut lossless = Vec::new();
+        encode(&mut lossless, Settings::quality(20).unwrap(), &image).unwrap();
+
+        let settings = Settings::quality(20).unwrap().with_alpha(Alpha::Lossy(table)).unwrap();
+        let mut lossy = Vec::new();
+        encode(&mut lossy, settings, &image).unwrap();
+        assert_ne!(lossy, lossless);
+
+        let decoded: Image<Rgba8> = decode(&lossy[..]).unwrap();
+        let max = image
+            .data()
+            .iter()
+            .zip(decoded.data())
+            .map(|(p, q)| (p.a as i32 - q.a as i32).abs())
+            .max()
+            .unwrap();
+        assert!(max > 0 && max < 32);
+
+        let result = Settings::new().with_alpha(Alpha::Lossy(Unit::new([0; 64])));
+        assert!(matches!(result, Err(Error::InvalidTable)));
+    }
+
+    #[test]
+    fn opaque_images_decode_as_opaque() {
+        let image = Image::new(9, 9, vec![Rgb8 { r: 10, g: 200, b: 30 }; 81]);
+        let mut data = Vec::new();
+        encode(&mut data, Settings::new(), &image).unwrap();
+
+        assert_eq!(Header::read(&mut &data[..]).unwrap().flags & header::FLAG_ALPHA, 0);
+
+        let decoded: Image<Rgba8> = decode(&data[..]).unwrap();
+        assert!(decoded.data().iter().all(|p| p.a == 255));
+    }
+
+    #[test]
+    fn twelve_bit_precision_keeps_high_depth_sources() {
+        // Gradients with steps much finer than 8 bits can represent
+        let data = (0..64 * 48)
+            .map(|i| (i % 64, i / 64))
+            .map(|(x, y)| Rgb16 { r: 20000 + x * 37, g: 30000 + y * 53, b: 40000 - (x + y) * 29 })
+            .collect();
+        let image = Image::new(64, 48, data);
+
+        let mean_error = |precision| {
+            let settings = Settings::quality(100).unwrap().with_precision(precision);
+            let mut data = Vec::new();
+            encode(&mut data, settings, &image).unwrap();
+
+            let header = Header::read(&mut &data[..]).unwrap();
+            assert_eq!(header.precision(), precision);
+
+            let decoded: Image<Rgb16> = decode(&data[..]).unwrap();
+            let sum: i64 = image
+                .data()
+                .iter()
+                .zip(decoded.data())
+                .flat_map(|(p, q)| [(p.r, q.r), (p.g, q.g), (p.b, q.b)])
+                .map(|(x, y)| (x as i64 - y as i64).abs())
+                .sum();
+
+            sum as f64 / (image.data().len() * 3) as f64
+        };
+
+        // One 8-bit step is 257 and one 12-bit step is 16 in 16-bit units
+        let coarse = mean_error(Precision::Bits8);
+        let fine = mean_error(Precision::Bits12);
+        assert!(coarse > 40.0);
+        assert!(fine < 16.0);
+    }
+
+    #[test]
+    fn dct_implementations_give_similar_quality() {
+        let image: Image<Rgb8> = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..]).unwrap();
+
+        let psnr = |dct| {
+            let mut data = Vec::new();
+            encode(&mut data, Settings::quality(90).unwrap().with_dct(dct), &image).unwrap();
+
+            let flags = Header::read(&mut &data[..]).unwrap().flags;
+            assert_eq!(flags & header::FLAG_INTEGER_DCT != 0, dct == DctImpl::Integer);
+
+            let decoded: Image<Rgb8> = decode(&data[..]).unwrap();
+            metrics::psnr(&image, &decoded).unwrap().combined
+        };
+
+        let fast = psnr(DctImpl::Fast);
+        for dct in [DctImpl::Float, DctImpl::Integer] {
+            assert!((psnr(dct) - fast).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn progressive_decodes_like_sequential() {
+        let rgb = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..]).unwrap();
+        let rgba = transparent_sample();
+
+        for subsampling in [Subsampling::S444, Subsampling::S420] {
+            let settings = Settings::quality(80).unwrap().with_subsampling(subsampling);
+
+            let mut sequential = Vec::new();
+            encode(&mut sequential, settings, &rgb).unwrap();
+            let mut progressive = Vec::new();
+            encode(&mut progressive, settings.with_progressive(true), &rgb).unwrap();
+
+            let header = Header::read(&mut &progressive[..]).unwrap();
+            assert_ne!(header.flags & header::FLAG_PROGRESSIVE, 0);
+
+            let expected: Image<Rgb8> = decode(&sequential[..]).unwrap();
+            let decoded: Image<Rgb8> = decode(&progressive[..]).unwrap();
+            assert_eq!(decoded.data(), expected.data());
+
+            let mut sequential = Vec::new();
+            encode(&mut sequential, settings, &rgba).unwrap();
+            let mut progressive = Vec::new();
+            encode(&mut progressive, settings.with_progressive(true), &rgba).unwrap();
+
+            let expected: Image<Rgba8> = decode(&sequential[..]).unwrap();
+            let decoded: Image<Rgba8> = decode(&progressive[..]).unwrap();
+            assert_eq!(decoded.data(), expected.data());
+        }
+    }
+
+    #[test]
+    fn partial_progressive_data_gives_previews() {
+        let image = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..]).unwrap();
+        let mut data = Vec::new();
+        encode(&mut data, Settings::quality(90).unwrap().with_progressive(true), &image).unwrap();
+
+        let (full, progress): (Image<Rgb8>, _) = decode_partial(&data[..]).unwrap();
+        assert_eq!(progress, Progress { scans: 4, total: 4 });
+        assert!(progress.is_complete());
+
+        // Nothing can be shown before the DC coefficients of all blocks are in
+        assert!(matches!(decode_partial::<_, Rgb8>(&data[..600]), Err(Error::Truncated)));
+        assert!(matches!(decode::<_, Rgb8>(&data[..data.len() - 1]), Err(Error::Truncated)));
+
+        let mut previous = (0, 0.0);
+        for len in (600..data.len()).step_by(173) {
+            let (preview, progress): (Image<Rgb8>, _) = match decode_partial(&data[..len]) {
+                Ok(result) => result,
+                Err(_) => continue,
+            };
+            assert!(!progress.is_complete());
+
+            let psnr = metrics::psnr(&full, &preview).unwrap().combined;
+            assert!(progress.scans >= previous.0);
+            if progress.scans > previous.0 {
+                assert!(psnr > previous.1);
+            }
+            previous = (progress.scans, psnr);
+        }
+
+        assert_eq!(previous.0, 3);
+    }
+
+    #[test]
+    fn progressive_dimensions_need_data() {
+        let image = Image::new(16, 16, vec![Lab8::default(); 256]);
+        let mut data = Vec::new();
+        encode(&mut data, Settings::default().with_progressive(true), &image).unwrap();
+
+        // Only a few hundred bytes claiming 2^20x2^20 pixels
+        data[9..17].copy_from_slice(&[0, 0x10, 0, 0, 0, 0x10, 0, 0]);
+        assert!(data.len() < 500);
+        // The data runs out or is misread long before the first scan ends
+        assert!(decode::<_, Lab8>(&data[..]).is_err());
+        assert!(decode_partial::<_, Lab8>(&data[..]).is_err());
+    }
+
+    #[test]
+    fn partial_decoding_needs_complete_sequential_data() {
+        let image = Image::new(16, 16, vec![Lab8::default(); 256]);
+        let mut data = Vec::new();
+        encode(&mut data, Settings::default(), &image).unwrap();
+
+        let (_, progress): (Image<Lab8>, _) = decode_partial(&data[..]).unwrap();
+        assert_eq!(progress, Progress { scans: 1, total: 1 });
+        assert!(matches!(decode_partial::<_, Lab8>(&data[..data.len() - 1]), Err(Error::Truncated)));
+
+        let encoder = RcrEncoder::<_, Lab8>::new(Vec::new(), Settings::default().with_progressive(true), 16, 16);
+        assert!(matches!(encoder, Err(Error::UnsupportedFeature(_))));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_output_matches_serial() {
+        let image = transparent_sample();
+        let serial = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+        let progressive = Settings::default().with_subsampling(Subsampling::S420).with_progressive(true);
+        for settings in [Settings::default(), progressive] {
+            let encoded = || {
+                let mut data = Vec::new();
+                encode(&mut data, settings, &image).unwrap();
+                data
+            };
+
+            let data = encoded();
+            assert_eq!(serial.install(encoded), data);
+
+            let decoded: Image<Rgba8> = decode(&data[..]).unwrap();
+            let expected: Image<Rgba8> = serial.install(|| decode(&data[..]).unwrap());
+            assert_eq!(decoded.data(), expected.data());
+        }
+    }
+
+    #[test]
+    fn settings_reject_invalid_tables() {
+        let (luma, chroma) = tables::from_quality(5).unwrap();
+        assert!(Settings::with_tables(luma, chroma).is_ok());
+
+        let mut table = [16; 64];
+        table[10] = 0;
+        let result = Settings::with_tables(luma, Unit::new(table));
+        assert!(matches!(result, Err(Error::InvalidTable)));
+
+        assert!(matches!(Settings::quality(0), Err(Error::InvalidQuality(0))));
+    }
+
+    #[test]
+    fn decode_rejects_foreign_data() {
+        let data = include_bytes!("../../../examples/in_192x192.ppm");
+        assert!(matches!(decode::<_, Rgb8>(&data[..]), Err(Error::InvalidMagic)));
+    }
+
+    #[test]
+    fn decode_rejects_truncated_data() {
+        let image = Image::new(8, 8, vec![Lab8::default(); 64]);
+        let mut data = Vec::new();
+        encode(&mut data, Settings::default(), &image).unwrap();
+
+        let result = decode::<_, Lab8>(&data[..data.len() - 1]);
+        assert!(matches!(result, Err(Error::Truncated)));
+    }
+
+    #[test]
+    fn decode_rejects_oversized_dimensions() {
+        let image = Image::new(8, 8, vec![Lab8::default(); 64]);
+        let mut data = Vec::new();
+        encode(&mut data, Settings::default(), &image).unwrap();
+
+        // Width and height follow the magic and five bytes of fields
+        data[9..17].fill(0xff);
+        let result = decode::<_, Rgb8>(&data[..]);
+        assert!(matches!(result, Err(Error::InvalidDimensions { width: 0xffffffff, height: 0xffffffff })));
+    }
+
+    #[test]
+    fn decode_rejects_wide_header_without_data() {
+        let image = Image::new(8, 8, vec![Lab8::default(); 64]);
+        let mut data = Vec::new();
+        encode(&mut data, Settings::default(), &image).unwrap();
+
+        // A band of this width would take gigabytes before any block is read
+        data[9..13].copy_from_slice(&0x40000000u32.to_be_bytes());
+        data[13..17].copy_from_slice(&1u32.to_be_bytes());
+        let result = decode::<_, Rgb8>(&data[..]);
+        assert!(matches!(result, Err(Error::Truncated)));
+    }
+
+    #[test]
+    fn decode_rejects_overflowing_coefficients() {
+        // Coded by hand, the encoder never quantizes to such values
+        let settings = Settings::with_tables(Unit::new([u16::MAX as i32; 64]), Unit::new([1; 64])).unwrap();
+        let frame = Frame::new::<Gray8>(8, 8, &settings).unwrap();
+        let mut block = [0; 64];
+        block[0] = 40000;
+        let blocks = [(0, block)];
+        let tables = Tables::new(&frame, frame.quantization(&settings), [(&blocks[..], &[][..])], false);
+
+        let mut data = Vec::new();
+        write_head(&mut data, &frame, &tables).unwrap();
+        assert_ne!(Header::read(&mut &data[..]).unwrap().flags & header::FLAG_WIDE_TABLES, 0);
+
+        let mut writer = BitWriter::new();
+        write_band(&mut writer, &frame, &tables, &blocks, &[], &mut [0]);
+        data.extend(writer.into_bytes());
+
+        assert!(matches!(decode::<_, Gray8>(&data[..]), Err(Error::InvalidData)));
+    }
+
+    #[test]
+    fn lossless_round_trip_is_exact() {
+        let samples: [&[u8]; 3] = [
+            include_bytes!("../../../examples/in_8x8.ppm"),
+            include_bytes!("../../../examples/in_16x16.ppm"),
+            include_bytes!("../../../examples/in_192x192.ppm"),
+        ];
+
+        // Settings of lossy coding do not apply
+        let settings = Settings::quality(10)
+            .unwrap()
+            .with_subsampling(Subsampling::S420)
+            .with_color_model(ColorModel::Bt601)
+            .with_progressive(true)
+            .with_lossless(true);
+
+        for sample in samples {
+            let image: Image<Rgb8> = ppm::decode(sample).unwrap();
+            // A partial last band and a width that is not a multiple of 8
+            let cropped = image.crop(image.width() - 3, image.height() - 5);
+
+            for image in [image, cropped] {
+                let mut data = Vec::new();
+                encode(&mut data, settings, &image).unwrap();
+
+                let header = Header::read(&mut &data[..]).unwrap();
+                assert_eq!(header.flags, header::FLAG_LOSSLESS);
+                assert_eq!(header.color_model, ColorModel::YCoCgR);
+                assert_eq!(header.subsampling, Subsampling::S444);
+
+                let decoded: Image<Rgb8> = decode(&data[..]).unwrap();
+                assert_eq!(decoded.data(), image.data());
+
+                let streamed = RcrDecoder::<_, Rgb8>::new(&data[..]).unwrap();
+                assert_eq!(read_image(streamed).unwrap().data(), image.data());
+            }
+        }
+
+        let image: Image<Rgb8> = ppm::decode(samples[2]).unwrap();
+        let mut data = Vec::new();
+        encode(&mut data, Settings::new().with_lossless(true), &image).unwrap();
+        assert!(data.len() < 3 * 192 * 192 * 3 / 4);
+    }
+
+    #[test]
+    fn lossless_gray_and_alpha_round_trip() {
+        let settings = Settings::new().with_lossless(true);
+
+        let gray: Image<Gray8> = ppm::decode_gray(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap();
+        let mut data = Vec::new();
+        encode(&mut data, settings, &gray).unwrap();
+        assert_eq!(Header::read(&mut &data[..]).unwrap().color_model, ColorModel::Gray);
+        let decoded: Image<Gray8> = decode(&data[..]).unwrap();
+        assert_eq!(decoded.data(), gray.data());
+
+        // Lossy alpha is replaced by lossless alpha
+        let image = transparent_sample();
+        let (table, _) = tables::scaled(50).unwrap();
+        let settings = settings.with_alpha(Alpha::Lossy(table)).unwrap();
+        let mut data = Vec::new();
+        encode(&mut data, settings, &image).unwrap();
+        let decoded: Image<Rgba8> = decode(&data[..]).unwrap();
+        assert_eq!(decoded.data(), image.data());
+    }
+}
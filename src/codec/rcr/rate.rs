@@ -0,0 +1,137 @@
+use crate::color::Pixel;
+use crate::error::{Error, Result};
+use crate::image::Image;
+use crate::metrics;
+
+use super::{Settings, Spectrum, decode, tables, write_spectrum};
+
+/// Goal of a rate controlled encode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Largest file not exceeding the given number of bytes
+    Size(usize),
+    /// Smallest file reaching the given combined PSNR in decibels
+    Psnr(f64),
+    /// Smallest file reaching the given combined SSIM
+    Ssim(f64),
+}
+
+/// Encodes the image at the quality (from 1 to 100) that best meets the
+/// target, found by a binary search over trial encodes. Everything but the
+/// luma and chroma quantization tables is taken from `settings`, those are
+/// replaced by scaled ones.
+///
+/// Returns the encoded file and the settings used to produce it, or
+/// `Error::TargetUnreachable` when no quality meets the target.
+pub fn encode_to_target<P>(
+    settings: Settings,
+    image: &Image<P>,
+    target: Target,
+) -> Result<(Vec<u8>, Settings)>
+where P: Pixel
+{
+    // Block transforms do not depend on quality, only quantization is redone
+    let spectrum = Spectrum::new(image, &settings)?;
+
+    let trial = |quality| -> Result<(Vec<u8>, Settings)> {
+        let (luma_table, chroma_table) = tables::scaled(quality)?;
+        let settings = Settings { luma_table, chroma_table, ..settings };
+        let mut data = Vec::new();
+        write_spectrum(&mut data, &settings, &spectrum)?;
+
+        Ok((data, settings))
+    };
+
+    let meets = |data: &[u8]| -> Result<bool> {
+        match target {
+            Target::Size(size) => Ok(data.len() <= size),
+            Target::Psnr(psnr) => Ok(metrics::psnr(image, &decode::<_, P>(data)?)?.combined >= psnr),
+            Target::Ssim(ssim) => Ok(metrics::ssim(image, &decode::<_, P>(data)?)?.combined >= ssim),
+        }
+    };
+
+    // Size is met by low qualities, quality targets by high ones
+    let low_meets = matches!(target, Target::Size(_));
+
+    let mut best = None;
+    let (mut lo, mut hi) = (1, 100);
+
+    while lo <= hi {
+        let quality = (lo + hi) / 2;
+        let (data, settings) = trial(quality)?;
+
+        match (meets(&data)?, low_meets) {
+            (true, true) => {
+                best = Some((data, settings));
+                lo = quality + 1;
+            }
+            (true, false) => {
+                best = Some((data, settings));
+                hi = quality - 1;
+            }
+            (false, true) => hi = quality - 1,
+            (false, false) => lo = quality + 1,
+        }
+    }
+
+    best.ok_or(Error::TargetUnreachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ppm;
+    use crate::codec::rcr::Subsampling;
+    use crate::color::{Lab8, Rgba8};
+
+    fn sample() -> Image<Lab8> {
+        ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..])
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn size_target() {
+        let image = sample();
+        let settings = Settings::new().with_subsampling(Subsampling::S420);
+        let (data, chosen) = encode_to_target(settings, &image, Target::Size(4000)).unwrap();
+
+        assert!(data.len() <= 4000);
+        assert!(data.len() > 3000);
+        assert_eq!(chosen.subsampling(), Subsampling::S420);
+
+        let mut again = Vec::new();
+        crate::codec::rcr::encode(&mut again, chosen, &image).unwrap();
+        assert_eq!(again, data);
+    }
+
+    #[test]
+    fn quality_targets() {
+        let image = sample();
+
+        let (data, _) = encode_to_target(Settings::new(), &image, Target::Psnr(36.0)).unwrap();
+        assert!(metrics::psnr(&image, &decode::<_, Lab8>(&data[..]).unwrap()).unwrap().combined >= 36.0);
+
+        let (data, _) = encode_to_target(Settings::new(), &image, Target::Ssim(0.9)).unwrap();
+        assert!(metrics::ssim(&image, &decode::<_, Lab8>(&data[..]).unwrap()).unwrap().combined >= 0.9);
+    }
+
+    #[test]
+    fn quality_targets_with_alpha() {
+        let image: Image<Rgba8> = sample().convert();
+
+        let (data, _) = encode_to_target(Settings::new(), &image, Target::Psnr(36.0)).unwrap();
+        assert!(metrics::psnr(&image, &decode::<_, Rgba8>(&data[..]).unwrap()).unwrap().combined >= 36.0);
+    }
+
+    #[test]
+    fn unreachable_targets() {
+        let image = sample();
+
+        let result = encode_to_target(Settings::new(), &image, Target::Size(100));
+        assert!(matches!(result, Err(Error::TargetUnreachable)));
+
+        let result = encode_to_target(Settings::new(), &image, Target::Psnr(100.0));
+        assert!(matches!(result, Err(Error::TargetUnreachable)));
+    }
+}
@@ -0,0 +1,150 @@
+use std::ops::Range;
+
+use crate::image::Image;
+
+/// Filter used to bring subsampled chroma planes back to full resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Upsampling {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+/// Averages `h`x`v` boxes of samples, the plane is extended by replicating
+/// its edges when its size is not a multiple of the box.
+pub fn downsample(plane: &Image<i32>, h: usize, v: usize) -> Image<i32> {
+    if h == 1 && v == 1 {
+        return plane.clone();
+    }
+
+    let width = plane.width().div_ceil(h);
+    let height = plane.height().div_ceil(v);
+    let mut data = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0;
+            for j in 0..v {
+                for i in 0..h {
+                    let px = (x * h + i).min(plane.width() - 1);
+                    let py = (y * v + j).min(plane.height() - 1);
+                    sum += plane.at(px, py);
+                }
+            }
+
+            data.push((sum as f32 / (h * v) as f32).round() as i32);
+        }
+    }
+
+    Image::new(width, height, data)
+}
+
+/// Scales a plane subsampled by `h`x`v` up to `width`x`height`. Samples are
+/// assumed to sit at the center of the box they were averaged from.
+pub fn upsample(
+    plane: &Image<i32>,
+    width: usize,
+    height: usize,
+    h: usize,
+    v: usize,
+    filter: Upsampling,
+) -> Image<i32> {
+    upsample_rows(plane, 0, plane.height(), width, 0..height, h, v, filter)
+}
+
+/// Like `upsample`, producing only the output `rows`. `plane` holds a window
+/// of the subsampled rows starting at `offset` out of `plane_height`, which
+/// has to cover all rows the filter reads.
+#[allow(clippy::too_many_arguments)]
+pub fn upsample_rows(
+    plane: &Image<i32>,
+    offset: usize,
+    plane_height: usize,
+    width: usize,
+    rows: Range<usize>,
+    h: usize,
+    v: usize,
+    filter: Upsampling,
+) -> Image<i32> {
+    let mut data = Vec::with_capacity(width * rows.len());
+
+    let last_x = plane.width() - 1;
+    let last_y = plane_height - 1;
+    let at = |x: usize, y: usize| *plane.at(x, y - offset);
+
+    for y in rows.clone() {
+        for x in 0..width {
+            let sample = match filter {
+                Upsampling::Nearest => at((x / h).min(last_x), (y / v).min(last_y)),
+                Upsampling::Bilinear => {
+                    let fx = ((x as f32 + 0.5) / h as f32 - 0.5).max(0.0);
+                    let fy = ((y as f32 + 0.5) / v as f32 - 0.5).max(0.0);
+
+                    let x0 = (fx as usize).min(last_x);
+                    let y0 = (fy as usize).min(last_y);
+                    let x1 = (x0 + 1).min(last_x);
+                    let y1 = (y0 + 1).min(last_y);
+                    let dx = fx - x0 as f32;
+                    let dy = fy - y0 as f32;
+
+                    let top = at(x0, y0) as f32 * (1.0 - dx) + at(x1, y0) as f32 * dx;
+                    let bottom = at(x0, y1) as f32 * (1.0 - dx) + at(x1, y1) as f32 * dx;
+
+                    (top * (1.0 - dy) + bottom * dy).round() as i32
+                }
+            };
+
+            data.push(sample);
+        }
+    }
+
+    Image::new(width, rows.len(), data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsample_averages_boxes() {
+        let plane = Image::new(3, 2, vec![0, 2, 10, 4, 6, 20]);
+        let half = downsample(&plane, 2, 2);
+
+        assert_eq!(half.width(), 2);
+        assert_eq!(half.height(), 1);
+        assert_eq!(half.data(), &vec![3, 15]);
+    }
+
+    #[test]
+    fn upsample_restores_flat_planes() {
+        let plane = Image::new(2, 2, vec![-7; 4]);
+
+        for filter in [Upsampling::Nearest, Upsampling::Bilinear] {
+            let full = upsample(&plane, 3, 4, 2, 2, filter);
+            assert_eq!(full.data(), &vec![-7; 12]);
+        }
+    }
+
+    #[test]
+    fn bilinear_interpolates_between_samples() {
+        let plane = Image::new(2, 1, vec![0, 40]);
+        let full = upsample(&plane, 4, 1, 2, 1, Upsampling::Bilinear);
+
+        assert_eq!(full.data(), &vec![0, 10, 30, 40]);
+    }
+
+    #[test]
+    fn upsample_rows_matches_upsample() {
+        let plane = Image::new(5, 9, (0..45).map(|i| (i * 37) % 101 - 50).collect());
+        let full = upsample(&plane, 10, 17, 2, 2, Upsampling::Bilinear);
+
+        // Windows with one row of context on each side, as used for bands
+        for (offset, window_rows, rows) in [(0, 0..9, 0..16), (7, 7..9, 16..17)] {
+            let data = plane.data()[5 * window_rows.start..5 * window_rows.end].to_vec();
+            let window = Image::new(5, window_rows.len(), data);
+            let part = upsample_rows(&window, offset, 9, 10, rows.clone(), 2, 2, Upsampling::Bilinear);
+
+            assert_eq!(part.data()[..], full.data()[10 * rows.start..10 * rows.end]);
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::VecDeque;
+use std::io::{BufReader, Read, Write};
+use std::marker::PhantomData;
+
+use crate::color::Pixel;
+use crate::error::{Error, Result};
+use crate::image::Image;
+use crate::parallel;
+
+use super::entropy::{BitReader, BitWriter, decode_block, decode_difference};
+use super::{
+    Alpha, Frame, Plane, Progress, Scanned, Settings, Tables, Upsampling,
+    alpha, decode_unit, lossless, read_head, read_scans, sampling, transform, write_band, write_head,
+};
+
+/// Encodes an image band by band, holding only the rows of the band in
+/// progress. A band is one row of MCUs, `band_height` rows of pixels.
+///
+/// Unlike `encode`, which fits the Huffman tables to the whole image, the
+/// tables are fit to the first band with every symbol given a code, so files
+/// are slightly larger. Progressive coding needs the whole image and is not
+/// supported.
+pub struct RcrEncoder<W, P> {
+    output: W,
+    settings: Settings,
+    frame: Frame,
+    /// Tables, written along with the header once the first band is complete
+    tables: Option<Tables>,
+    writer: BitWriter,
+    prev_dc: Vec<i32>,
+    /// Normalized pixels of the band in progress
+    pending: Vec<[f32; 4]>,
+    /// Normalized pixels of the last row of the previous band
+    above: Vec<[f32; 4]>,
+    /// Number of rows written so far
+    rows: usize,
+    pixel: PhantomData<P>,
+}
+
+impl<W, P> RcrEncoder<W, P>
+where
+    W: Write,
+    P: Pixel,
+{
+    /// Starts encoding an image of the given dimensions, pixel types are
+    /// handled as in `encode`.
+    pub fn new(output: W, settings: Settings, width: usize, height: usize) -> Result<Self> {
+        if settings.progressive {
+            return Err(Error::UnsupportedFeature("progressive streaming"));
+        }
+
+        let frame = Frame::new::<P>(width, height, &settings)?;
+        let prev_dc = vec![0; frame.layout.planes.len()];
+
+        Ok(Self {
+            output,
+            settings,
+            frame,
+            tables: None,
+            writer: BitWriter::new(),
+            prev_dc,
+            pending: Vec::new(),
+            above: Vec::new(),
+            rows: 0,
+            pixel: PhantomData,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.frame.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.frame.height
+    }
+
+    /// Number of rows coded together, 16 with 4:2:0 subsampling and 8
+    /// otherwise.
+    pub fn band_height(&self) -> usize {
+        self.frame.band_height()
+    }
+
+    /// Adds whole rows of pixels, any number at a time. Bands are coded and
+    /// written to the output as soon as all their rows are in.
+    pub fn write_rows(&mut self, pixels: &[P]) -> Result<()> {
+        let width = self.frame.width;
+        if !pixels.len().is_multiple_of(width) || self.rows + pixels.len() / width > self.frame.height {
+            return Err(Error::DimensionMismatch);
+        }
+
+        for row in pixels.chunks(width) {
+            self.pending.extend(row.iter().map(|p| p.to_normalized()));
+            self.rows += 1;
+
+            if self.rows.is_multiple_of(self.frame.band_height()) || self.rows == self.frame.height {
+                self.write_band()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_band(&mut self) -> Result<()> {
+        let width = self.frame.width;
+        let pixels = Image::new(width, self.pending.len() / width, std::mem::take(&mut self.pending));
+
+        let band = self.frame.analyze(&pixels, &self.above);
+        self.above = pixels.data()[pixels.data().len() - width..].to_vec();
+
+        let tables = match self.tables.take() {
+            Some(tables) => tables,
+            None => {
+                let quantization = self.frame.quantization(&self.settings);
+                let blocks = self.frame.quantize(&band, &quantization);
+                let tables = Tables::new(&self.frame, quantization, [(&blocks[..], &band.residuals[..])], true);
+
+                write_head(&mut self.output, &self.frame, &tables)?;
+                tables
+            }
+        };
+
+        let blocks = self.frame.quantize(&band, &tables.quantization);
+        write_band(&mut self.writer, &self.frame, &tables, &blocks, &band.residuals, &mut self.prev_dc);
+        self.tables = Some(tables);
+
+        self.output.write_all(&self.writer.take_bytes())?;
+
+        Ok(())
+    }
+
+    /// Writes the end of the coded data and returns the output. Fails with
+    /// `Error::Truncated` if not all rows were written.
+    pub fn finish(mut self) -> Result<W> {
+        if self.rows < self.frame.height {
+            return Err(Error::Truncated);
+        }
+
+        self.output.write_all(&self.writer.into_bytes())?;
+
+        Ok(self.output)
+    }
+}
+
+/// Planes of one decoded band, covering whole MCUs.
+struct DecodedBand {
+    planes: Vec<Image<i32>>,
+    /// Alpha samples of the rows of the band
+    alpha: Option<Vec<u8>>,
+}
+
+/// Decodes an image band by band. Besides the band being returned, the
+/// decoder holds the previous and the next band, which bilinear upsampling of
+/// chroma reads from. The coefficients of progressive images are all read up
+/// front, so memory use is only bounded for sequential images.
+pub struct RcrDecoder<R, P> {
+    reader: BitReader<BufReader<R>>,
+    frame: Frame,
+    tables: Tables,
+    upsampling: Upsampling,
+    prev_dc: Vec<i32>,
+    /// Number of bands decoded so far
+    decoded: usize,
+    /// Decoded bands not yet returned
+    pending: VecDeque<DecodedBand>,
+    /// Last band returned
+    previous: Option<DecodedBand>,
+    /// Alpha samples of the last decoded row, the context of lossless alpha
+    above: Vec<u8>,
+    /// Samples of the last decoded row of every lossless color plane
+    above_samples: Vec<Vec<i32>>,
+    /// Coefficients of a progressive image
+    scanned: Option<Scanned>,
+    progress: Progress,
+    pixel: PhantomData<P>,
+}
+
+impl<R, P> RcrDecoder<R, P>
+where
+    R: Read,
+    P: Pixel,
+{
+    /// Reads the header and tables, pixel types are handled as in `decode`.
+    pub fn new(input: R) -> Result<Self> {
+        Self::with_upsampling(input, Upsampling::default())
+    }
+
+    /// Like `new`, scaling subsampled chroma planes with the given filter.
+    pub fn with_upsampling(input: R, upsampling: Upsampling) -> Result<Self> {
+        Self::open(input, upsampling, false)
+    }
+
+    /// Like `new`, but progressive images may end after any complete scan,
+    /// see `progress`.
+    pub fn partial(input: R) -> Result<Self> {
+        Self::open(input, Upsampling::default(), true)
+    }
+
+    fn open(input: R, upsampling: Upsampling, partial: bool) -> Result<Self> {
+        let mut input = BufReader::new(input);
+        let (frame, tables) = read_head(&mut input)?;
+        let prev_dc = vec![0; frame.layout.planes.len()];
+        let above_samples = vec![Vec::new(); frame.layout.planes.len()];
+
+        let mut reader = BitReader::new(input);
+        let scanned = match frame.progressive() {
+            true => Some(read_scans(&mut reader, &frame, &tables, partial)?),
+            false => None,
+        };
+        let progress = Progress {
+            scans: scanned.as_ref().map_or(1, |scanned| scanned.scans),
+            total: frame.scans.len(),
+        };
+
+        Ok(Self {
+            reader,
+            frame,
+            tables,
+            upsampling,
+            prev_dc,
+            decoded: 0,
+            pending: VecDeque::new(),
+            previous: None,
+            above: Vec::new(),
+            above_samples,
+            scanned,
+            progress,
+            pixel: PhantomData,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.frame.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.frame.height
+    }
+
+    /// Number of scans available for decoding, those of progressive images
+    /// are read when opening the file.
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    /// Number of rows of every band but the last, which may be shorter.
+    pub fn band_height(&self) -> usize {
+        self.frame.band_height()
+    }
+
+    fn decode_band(&mut self) -> Result<()> {
+        let layout = &self.frame.layout;
+        let slots = layout.tables();
+
+        // Everything is read before the planes are allocated, so dimensions
+        // in the header cannot exhaust memory without the data to match
+        let mut blocks = Vec::new();
+        let coefficients = match &mut self.scanned {
+            Some(scanned) => {
+                let coefficients = scanned.bands.pop_front().ok_or(Error::InvalidData)?;
+                blocks.extend(layout.band_blocks().take(coefficients.len()));
+                coefficients
+            }
+            None if self.frame.lossless => Vec::new(),
+            None => {
+                let mut coefficients = Vec::new();
+                for (c, x, y) in layout.band_blocks() {
+                    let (dc, ac) = &self.tables.huffman[slots[c]];
+                    let block = decode_block(&mut self.reader, self.prev_dc[c], dc, ac)?;
+                    self.prev_dc[c] = block[0];
+
+                    coefficients.push(block);
+                    blocks.push((c, x, y));
+                }
+
+                coefficients
+            }
+        };
+
+        let width = self.frame.width;
+        let rows = self.frame.band_rows(self.decoded);
+
+        // Lossless color planes precede the alpha prediction errors
+        let mut lossless_rows = Vec::new();
+        if let (true, Some(table)) = (self.frame.lossless, &self.tables.residual) {
+            for samples in &mut self.above_samples {
+                let start = samples.len();
+                lossless::reconstruct(samples, width, rows.len() * width, || {
+                    decode_difference(&mut self.reader, table)
+                })?;
+
+                let band = samples.split_off(start);
+                *samples = band[band.len() - width..].to_vec();
+                lossless_rows.push(band);
+            }
+        }
+
+        let mut planes: Vec<Image<i32>> = (0..layout.planes.len())
+            .map(|c| layout.band_size(c))
+            .map(|(w, h)| Image::new(w, h, vec![0; w * h]))
+            .collect();
+
+        let quantization = &self.tables.quantization;
+        let samples = parallel::map_range(0..blocks.len(), |k| {
+            decode_unit(coefficients[k], quantization[slots[blocks[k].0]], self.frame.dct)
+        });
+        let samples = samples.into_iter().collect::<Result<Vec<_>>>()?;
+
+        for (&(c, x, y), block) in blocks.iter().zip(&samples) {
+            let plane = &mut planes[c];
+            let stride = plane.width();
+
+            for j in 0..8 {
+                for i in 0..8 {
+                    plane.data_mut()[(8 * x + i) + (8 * y + j) * stride] = block[i + 8 * j];
+                }
+            }
+        }
+
+        for (plane, band) in planes.iter_mut().zip(&lossless_rows) {
+            let stride = plane.width();
+            for (y, row) in band.chunks(width).enumerate() {
+                plane.data_mut()[y * stride..][..width].copy_from_slice(row);
+            }
+        }
+
+        let alpha = match (self.frame.alpha, &self.tables.residual, &self.scanned) {
+            (Some(Alpha::Lossless), Some(_), Some(scanned)) => scanned.alpha
+                .as_ref()
+                .map(|alpha| alpha[rows.start * width..rows.end * width].to_vec()),
+            (Some(Alpha::Lossless), Some(table), None) => {
+                let rows = rows.len();
+                let start = self.above.len();
+                alpha::reconstruct(&mut self.above, width, rows * width, || {
+                    decode_difference(&mut self.reader, table)
+                })?;
+
+                let samples = self.above.split_off(start);
+                self.above = samples[samples.len() - width..].to_vec();
+                Some(samples)
+            }
+            _ => layout.planes
+                .iter()
+                .position(|&kind| kind == Plane::Alpha)
+                .map(|c| planes[c]
+                    .crop(width, rows.len())
+                    .data()
+                    .iter()
+                    .map(|&x| (x + 128).clamp(u8::MIN as i32, u8::MAX as i32) as u8)
+                    .collect()
+                ),
+        };
+
+        self.pending.push_back(DecodedBand { planes, alpha });
+        self.decoded += 1;
+
+        Ok(())
+    }
+
+    /// Decodes the next band, returning `None` after the last one.
+    pub fn read_band(&mut self) -> Result<Option<Image<P>>> {
+        // One band of lookahead for the chroma rows below the band
+        while self.decoded < self.frame.bands() && self.pending.len() < 2 {
+            self.decode_band()?;
+        }
+
+        let band = match self.pending.pop_front() {
+            Some(band) => band,
+            None => return Ok(None),
+        };
+
+        let m = self.decoded - self.pending.len() - 1;
+        let rows = self.frame.band_rows(m);
+        let width = self.frame.width;
+
+        let (h, v) = self.frame.subsampling.factors();
+        let chroma_width = width.div_ceil(h);
+        let chroma_height = self.frame.height.div_ceil(v);
+        let row = |plane: &Image<i32>, y: usize| plane.data()[y * plane.width()..][..chroma_width].to_vec();
+
+        let l = band.planes[0].crop(width, rows.len());
+        let chroma: Vec<Image<i32>> = self.frame.layout.planes
+            .iter()
+            .enumerate()
+            .filter(|(_, &kind)| kind == Plane::Chroma)
+            .map(|(c, _)| {
+                // Window of chroma rows with a neighbouring row on each side
+                let mut offset = 8 * m;
+                let mut data = Vec::new();
+
+                if let Some(previous) = &self.previous {
+                    data.extend(row(&previous.planes[c], 7));
+                    offset -= 1;
+                }
+                for y in 0..(chroma_height - 8 * m).min(8) {
+                    data.extend(row(&band.planes[c], y));
+                }
+                if let Some(next) = self.pending.front() {
+                    data.extend(row(&next.planes[c], 0));
+                }
+
+                let window = Image::new(chroma_width, data.len() / chroma_width, data);
+                sampling::upsample_rows(&window, offset, chroma_height, width, rows.clone(), h, v, self.upsampling)
+            })
+            .collect();
+
+        let color_model = self.frame.color_model;
+        let precision = self.frame.precision;
+        let max = precision.max() as f32;
+
+        let data: Vec<P> = parallel::map_range(0..width * rows.len(), |i| {
+            let mut samples = [l.data()[i], 0, 0];
+            for (x, plane) in samples[1..].iter_mut().zip(&chroma) {
+                *x = plane.data()[i];
+            }
+
+            let [r, g, b] = transform::inverse(color_model, precision, samples)
+                .map(|x| x as f32 / max);
+            let a = band.alpha.as_ref().map_or(u8::MAX, |alpha| alpha[i]) as f32 / u8::MAX as f32;
+
+            P::from_normalized([r, g, b, a])
+        });
+
+        self.previous = Some(band);
+
+        Ok(Some(Image::new(width, rows.len(), data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::ppm;
+    use crate::codec::rcr::{Subsampling, decode, encode};
+    use crate::color::{Rgb8, Rgba8};
+
+    /// Sample image cropped so that the last band is partial.
+    fn sample() -> Image<Rgba8> {
+        let image = ppm::decode(&include_bytes!("../../../examples/in_192x192.ppm")[..]).unwrap();
+        let image = image.crop(181, 150);
+        let data = image
+            .data()
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| Rgba8 { a: ((i % 181) * 255 / 180) as u8, ..p.into() })
+            .collect();
+
+        Image::new(181, 150, data)
+    }
+
+    fn encode_stream<P>(settings: Settings, image: &Image<P>, chunk: usize) -> Result<Vec<u8>>
+    where P: Pixel
+    {
+        let mut encoder = RcrEncoder::new(Vec::new(), settings, image.width(), image.height())?;
+        for rows in image.data().chunks(chunk * image.width()) {
+            encoder.write_rows(rows)?;
+        }
+
+        encoder.finish()
+    }
+
+    #[test]
+    fn streamed_encode_decodes_like_encode() {
+        let rgba = sample();
+        let rgb: Image<Rgb8> = rgba.convert();
+
+        for subsampling in [Subsampling::S444, Subsampling::S420] {
+            let settings = Settings::quality(60).unwrap().with_subsampling(subsampling);
+
+            // Only the Huffman tables differ, the decoded pixels are the same
+            let mut data = Vec::new();
+            encode(&mut data, settings, &rgb).unwrap();
+            let streamed = encode_stream(settings, &rgb, 7).unwrap();
+            let expected: Image<Rgb8> = decode(&data[..]).unwrap();
+            let decoded: Image<Rgb8> = decode(&streamed[..]).unwrap();
+            assert_eq!(decoded.data(), expected.data());
+            // Tables with a code for every symbol take up about a kilobyte more
+            assert!(streamed.len() < data.len() + 1500);
+
+            let mut data = Vec::new();
+            encode(&mut data, settings, &rgba).unwrap();
+            let streamed = encode_stream(settings, &rgba, 13).unwrap();
+            let expected: Image<Rgba8> = decode(&data[..]).unwrap();
+            let decoded: Image<Rgba8> = decode(&streamed[..]).unwrap();
+            assert_eq!(decoded.data(), expected.data());
+            assert!(decoded.data().iter().zip(rgba.data()).all(|(p, q)| p.a == q.a));
+        }
+    }
+
+    #[test]
+    fn streamed_lossless_is_exact() {
+        let rgba = sample();
+        let rgb: Image<Rgb8> = rgba.convert();
+        let settings = Settings::new().with_lossless(true);
+
+        let streamed = encode_stream(settings, &rgb, 5).unwrap();
+        let decoded: Image<Rgb8> = decode(&streamed[..]).unwrap();
+        assert_eq!(decoded.data(), rgb.data());
+
+        let streamed = encode_stream(settings, &rgba, 11).unwrap();
+        let decoded: Image<Rgba8> = decode(&streamed[..]).unwrap();
+        assert_eq!(decoded.data(), rgba.data());
+    }
+
+    #[test]
+    fn decoder_returns_bands() {
+        let image = sample();
+        let settings = Settings::quality(75).unwrap().with_subsampling(Subsampling::S420);
+        let mut data = Vec::new();
+        encode(&mut data, settings, &image).unwrap();
+
+        let mut decoder: RcrDecoder<_, Rgba8> = RcrDecoder::new(&data[..]).unwrap();
+        assert_eq!((decoder.width(), decoder.height(), decoder.band_height()), (181, 150, 16));
+
+        let mut heights = Vec::new();
+        while let Some(band) = decoder.read_band().unwrap() {
+            assert_eq!(band.width(), 181);
+            heights.push(band.height());
+        }
+
+        assert_eq!(heights.len(), 10);
+        assert!(heights[..9].iter().all(|&h| h == 16));
+        assert_eq!(heights[9], 6);
+        assert!(decoder.read_band().unwrap().is_none());
+    }
+
+    #[test]
+    fn encoder_checks_rows() {
+        let image = sample();
+        let pixels = image.data();
+
+        let mut encoder = RcrEncoder::new(Vec::new(), Settings::default(), 181, 150).unwrap();
+        assert!(matches!(encoder.write_rows(&pixels[..100]), Err(Error::DimensionMismatch)));
+        assert!(matches!(encoder.write_rows(&pixels[..181 * 149]), Ok(())));
+        assert!(matches!(encoder.write_rows(&pixels[..362]), Err(Error::DimensionMismatch)));
+        assert!(matches!(encoder.finish(), Err(Error::Truncated)));
+
+        let encoder: Result<RcrEncoder<_, Rgba8>> = RcrEncoder::new(Vec::new(), Settings::default(), 0, 8);
+        assert!(matches!(encoder, Err(Error::InvalidDimensions { width: 0, height: 8 })));
+    }
+}
@@ -1,9 +1,16 @@
+use std::io::{Read, Write};
+
 use super::unit::*;
+use crate::error::{Error, Result};
 
 type Table = [i32; 64];
 
-pub fn from_quality(quality: usize) -> (Unit<i32>, Unit<i32>) {
-    let (chroma, luma) = match quality {
+/// Largest quantizer the file format can store.
+pub const MAX_QUANTIZER: i32 = u16::MAX as i32;
+
+/// Returns the preset `(luma, chroma)` tables for levels 0 to 12.
+pub fn from_quality(quality: usize) -> Result<(Unit<i32>, Unit<i32>)> {
+    let (luma, chroma) = match quality {
         0 => (LUMA_Q0, CHROMA_Q0),
         1 => (LUMA_Q1, CHROMA_Q1),
         2 => (LUMA_Q2, CHROMA_Q2),
@@ -12,19 +19,111 @@ pub fn from_quality(quality: usize) -> (Unit<i32>, Unit<i32>) {
         5 => (LUMA_Q5, CHROMA_Q5),
         6 => (LUMA_Q6, CHROMA_Q6),
         7 => (LUMA_Q7, CHROMA_Q7),
+        8 => (LUMA_Q8, CHROMA_Q8),
+        9 => (LUMA_Q9, CHROMA_Q9),
+        10 => (LUMA_Q10, CHROMA_Q10),
+        11 => (LUMA_Q11, CHROMA_Q11),
+        12 => (LUMA_Q12, CHROMA_Q12),
+        _ => return Err(Error::InvalidQuality(quality)),
+    };
+
+    Ok((Unit::new(luma), Unit::new(chroma)))
+}
 
-        // TODO: Not working properly due to clipping
-        // 8 => (LUMA_Q8, CHROMA_Q8),
-        // 9 => (LUMA_Q9, CHROMA_Q9),
-        // 10 => (LUMA_Q10, CHROMA_Q10),
-        // 11 => (LUMA_Q11, CHROMA_Q11),
-        // 12 => (LUMA_Q12, CHROMA_Q12),
-        _ => panic!("Table quality out of range")
+/// Scales the base tables for a quality from 1 to 100 following the IJG
+/// formula, 50 keeps the base tables and 100 quantizes every coefficient by 1.
+pub fn scaled(quality: usize) -> Result<(Unit<i32>, Unit<i32>)> {
+    if !(1..=100).contains(&quality) {
+        return Err(Error::InvalidQuality(quality));
+    }
+
+    let quality = quality as i32;
+    let scale = match quality < 50 {
+        true => 5000 / quality,
+        false => 200 - 2 * quality,
     };
 
-    (Unit::new(chroma), Unit::new(luma))
+    let helper = |table: Table| Unit::new(table.map(|x| ((x * scale + 50) / 100).clamp(1, MAX_QUANTIZER)));
+
+    Ok((helper(LUMA_BASE), helper(CHROMA_BASE)))
+}
+
+/// Checks that every quantizer can be stored and used as a divisor.
+pub fn validate(table: &Unit<i32>) -> Result<()> {
+    match table.into_iter().all(|x| (1..=MAX_QUANTIZER).contains(&x)) {
+        true => Ok(()),
+        false => Err(Error::InvalidTable),
+    }
 }
 
+/// Whether the table needs 16-bit entries to be stored.
+pub fn is_wide(table: &Unit<i32>) -> bool {
+    table.into_iter().any(|x| x > u8::MAX as i32)
+}
+
+/// Writes the table in row-major order with 8 or 16-bit big endian entries.
+pub fn write<W>(output: &mut W, table: &Unit<i32>, wide: bool) -> Result<()>
+where W: Write
+{
+    let data: Vec<u8> = match wide {
+        true => table.into_iter().flat_map(|x| (x as u16).to_be_bytes()).collect(),
+        false => table.into_iter().map(|x| x as u8).collect(),
+    };
+    output.write_all(&data)?;
+
+    Ok(())
+}
+
+pub fn read<R>(input: &mut R, wide: bool) -> Result<Unit<i32>>
+where R: Read
+{
+    let mut table = [0; 64];
+
+    if wide {
+        let mut bytes = [0; 128];
+        input.read_exact(&mut bytes)?;
+        for (x, b) in table.iter_mut().zip(bytes.chunks_exact(2)) {
+            *x = u16::from_be_bytes([b[0], b[1]]) as i32;
+        }
+    }
+    else {
+        let mut bytes = [0; 64];
+        input.read_exact(&mut bytes)?;
+        for (x, &b) in table.iter_mut().zip(&bytes) {
+            *x = b as i32;
+        }
+    }
+
+    let table = Unit::new(table);
+    validate(&table)?;
+
+    Ok(table)
+}
+
+/// Luminance table from the JPEG standard (Annex K), corresponding to quality 50.
+pub const LUMA_BASE: Table = [
+    16, 11, 10, 16,  24,  40,  51,  61,
+    12, 12, 14, 19,  26,  58,  60,  55,
+    14, 13, 16, 24,  40,  57,  69,  56,
+    14, 17, 22, 29,  51,  87,  80,  62,
+    18, 22, 37, 56,  68, 109, 103,  77,
+    24, 35, 55, 64,  81, 104, 113,  92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103,  99,
+];
+
+/// Chrominance table from the JPEG standard (Annex K), corresponding to quality 50.
+pub const CHROMA_BASE: Table = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
 pub const LUMA_Q0: Table = [
     32, 33, 51, 81, 66, 39, 34, 17,
     33, 36, 48, 47, 28, 23, 12, 12,
@@ -309,4 +408,47 @@ pub const CHROMA_Q12: Table = [
     3, 3, 3, 3, 3, 3, 3, 3, 
     3, 3, 3, 3, 3, 3, 3, 3, 
     3, 3, 3, 3, 3, 3, 3, 3,
-];
\ No newline at end of file
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_tables_follow_quality() {
+        let (luma, chroma) = scaled(50).unwrap();
+        assert_eq!(luma, Unit::new(LUMA_BASE));
+        assert_eq!(chroma, Unit::new(CHROMA_BASE));
+
+        let (luma, chroma) = scaled(100).unwrap();
+        assert_eq!(luma, Unit::new([1; 64]));
+        assert_eq!(chroma, Unit::new([1; 64]));
+
+        let (coarse, _) = scaled(10).unwrap();
+        let (fine, _) = scaled(90).unwrap();
+        assert!(coarse.into_iter().zip(fine).all(|(a, b)| a >= b));
+        assert!(validate(&coarse).is_ok());
+    }
+
+    #[test]
+    fn table_serialization_equality() {
+        let (coarse, _) = scaled(1).unwrap();
+        let (fine, _) = scaled(95).unwrap();
+        assert!(is_wide(&coarse));
+        assert!(!is_wide(&fine));
+
+        for (table, wide) in [(coarse, true), (fine, false)] {
+            let mut data = Vec::new();
+            write(&mut data, &table, wide).unwrap();
+            assert_eq!(data.len(), if wide { 128 } else { 64 });
+            assert_eq!(read(&mut &data[..], wide).unwrap(), table);
+        }
+    }
+
+    #[test]
+    fn out_of_range_quality_is_rejected() {
+        assert!(matches!(scaled(0), Err(Error::InvalidQuality(0))));
+        assert!(matches!(scaled(101), Err(Error::InvalidQuality(101))));
+        assert!(matches!(from_quality(13), Err(Error::InvalidQuality(13))));
+    }
+}
@@ -0,0 +1,165 @@
+use super::header::{ColorModel, Precision};
+
+/// Red and blue luma weights of the YCbCr variants, grayscale uses the BT.709
+/// luma like `color::Gray8`.
+fn weights(model: ColorModel) -> Option<(f32, f32)> {
+    match model {
+        ColorModel::Bt601 => Some((0.299, 0.114)),
+        ColorModel::Bt709 | ColorModel::Gray => Some((0.2126, 0.0722)),
+        ColorModel::Bt2020 => Some((0.2627, 0.0593)),
+        ColorModel::YCoCgR => None,
+    }
+}
+
+/// Converts RGB samples from 0 to `precision.max()` into luma and two chroma
+/// samples, with luma shifted to be centered around zero. Chroma samples of
+/// grayscale are always zero.
+pub fn forward(model: ColorModel, precision: Precision, rgb: [i32; 3]) -> [i32; 3] {
+    let [r, g, b] = rgb;
+    let offset = 1 << (precision.bits() - 1);
+
+    match weights(model) {
+        Some((kr, kb)) => {
+            let (r, g, b) = (r as f32, g as f32, b as f32);
+            let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+
+            if model == ColorModel::Gray {
+                return [(y - offset as f32).round() as i32, 0, 0];
+            }
+
+            [
+                y - offset as f32,
+                (b - y) / (2.0 * (1.0 - kb)),
+                (r - y) / (2.0 * (1.0 - kr)),
+            ].map(|x| x.round() as i32)
+        }
+        None => {
+            let co = r - b;
+            let t = b + (co >> 1);
+            let cg = g - t;
+
+            [t + (cg >> 1) - offset, co, cg]
+        }
+    }
+}
+
+/// Inverse of `forward`, samples out of the RGB range are clamped.
+pub fn inverse(model: ColorModel, precision: Precision, samples: [i32; 3]) -> [i32; 3] {
+    let offset = 1 << (precision.bits() - 1);
+    let clamp = |x: i32| x.clamp(0, precision.max());
+
+    if model == ColorModel::Gray {
+        return [clamp(samples[0] + offset); 3];
+    }
+
+    match weights(model) {
+        Some((kr, kb)) => {
+            let [y, cb, cr] = samples.map(|x| x as f32);
+            let y = y + offset as f32;
+            let r = y + 2.0 * (1.0 - kr) * cr;
+            let b = y + 2.0 * (1.0 - kb) * cb;
+            let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+
+            [r, g, b].map(|x| clamp(x.round() as i32))
+        }
+        None => {
+            let [y, co, cg] = samples;
+            let t = y + offset - (cg >> 1);
+            let g = cg + t;
+            let b = t - (co >> 1);
+            let r = b + co;
+
+            [r, g, b].map(clamp)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Lab8, Rgb8};
+
+    fn rgb(p: Rgb8) -> [i32; 3] {
+        [p.r as i32, p.g as i32, p.b as i32]
+    }
+
+    const MODELS: [ColorModel; 5] = [
+        ColorModel::Bt601,
+        ColorModel::Bt709,
+        ColorModel::Bt2020,
+        ColorModel::YCoCgR,
+        ColorModel::Gray,
+    ];
+
+    fn colors() -> impl Iterator<Item = Rgb8> {
+        (0..=255).step_by(5).flat_map(|r| {
+            (0..=255).step_by(15).flat_map(move |g| {
+                (0..=255).step_by(17).map(move |b| Rgb8 { r, g, b })
+            })
+        })
+    }
+
+    fn grays() -> impl Iterator<Item = Rgb8> {
+        (0..=255).map(|v| Rgb8 { r: v, g: v, b: v })
+    }
+
+    #[test]
+    fn round_trip_error() {
+        for model in MODELS {
+            let inputs: Vec<Rgb8> = match model {
+                ColorModel::Gray => grays().collect(),
+                _ => colors().collect(),
+            };
+
+            let max = inputs
+                .into_iter()
+                .map(|p| (rgb(p), inverse(model, Precision::Bits8, forward(model, Precision::Bits8, rgb(p)))))
+                .map(|(p, q)| (0..3).map(|c| (p[c] - q[c]).abs()).max().unwrap())
+                .max()
+                .unwrap();
+
+            match model {
+                ColorModel::YCoCgR | ColorModel::Gray => assert_eq!(max, 0),
+                _ => assert!(max <= 1, "{:?} {}", model, max),
+            }
+        }
+    }
+
+    #[test]
+    fn bt709_matches_lab8() {
+        for p in colors() {
+            let lab = Lab8::from(p);
+            let [l, a, b] = forward(ColorModel::Bt709, Precision::Bits8, rgb(p));
+
+            assert!((l - lab.l as i32).abs() <= 1);
+            assert!((a - lab.a as i32).abs() <= 1);
+            assert!((b - lab.b as i32).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn bt601_matches_jpeg() {
+        // JPEG stores these as Y = 76, Cb = 85 and Cr = 255 after clamping
+        let red = forward(ColorModel::Bt601, Precision::Bits8, [255, 0, 0]);
+        assert_eq!(red, [76 - 128, 85 - 128, 128]);
+
+        let gray = forward(ColorModel::Bt601, Precision::Bits8, [128, 128, 128]);
+        assert_eq!(gray, [0, 0, 0]);
+    }
+
+    #[test]
+    fn twelve_bit_round_trip() {
+        for model in MODELS {
+            for p in colors() {
+                let p = rgb(p).map(|x| x * 16 + x / 16);
+                let q = inverse(model, Precision::Bits12, forward(model, Precision::Bits12, p));
+
+                match model {
+                    ColorModel::Gray => assert_eq!(q[0], forward(model, Precision::Bits12, p)[0] + 2048),
+                    ColorModel::YCoCgR => assert_eq!(q, p),
+                    _ => assert!((0..3).all(|c| (p[c] - q[c]).abs() <= 1)),
+                }
+            }
+        }
+    }
+}
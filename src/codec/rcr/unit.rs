@@ -1,5 +1,8 @@
 use std::ops::{Div, Mul};
 
+use super::aan;
+use crate::error::{Error, Result};
+
 // Precomputed table
 const COS_MUL_F: [f32; 64] = [
     /*
@@ -36,7 +39,16 @@ const COS_MUL_F: [f32; 64] = [
     0.353553, -0.490392,  0.461939, -0.415734,  0.353553, -0.277785,  0.191341, -0.097545,
 ];
 
-const DCT_MUL_SHIFT: i32 = 21; // 32 - <sign bit> - 10 (due to DCT output range for u8)
+/// Fractional bits of `DCT_MUL_I`.
+const DCT_MUL_SHIFT: u32 = 21;
+
+/// Fractional bits kept between the passes of the integer transforms.
+const PASS_BITS: u32 = 8;
+
+/// Bound of the magnitude of dequantized coefficients. Those of 12-bit
+/// samples stay below 2^15, even after rounding to a multiple of the largest
+/// quantizer, and larger ones could overflow the integer transforms.
+pub const MAX_COEFFICIENT: i32 = 1 << 16;
 
 // Precomputed table
 const DCT_MUL_I: [i32; 64] = [
@@ -74,6 +86,48 @@ const DCT_MUL_I: [i32; 64] = [
     741455, -1028427,  968757,  -871859,  741455,  -582557,  401273,  -204567,
 ];
 
+/// Implementation of the DCT used by the RCR codec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DctImpl {
+    /// Matrix transform in `f32`, `Unit::<f32>::dct`
+    Float,
+    /// Fixed-point matrix transform, `Unit::<i32>::dct`. Files are marked so
+    /// that decoders use the matching inverse, which gives the same samples
+    /// on every platform.
+    Integer,
+    /// Factored transform in `f32`, `Unit::<f32>::fast_dct`
+    #[default]
+    Fast,
+}
+
+/// Shifts right by `bits`, rounding halves up.
+fn descale(x: i64, bits: u32) -> i64 {
+    (x + (1 << (bits - 1))) >> bits
+}
+
+/// Division rounding to the nearest value, with halves away from zero.
+pub trait DivRound {
+    fn div_round(self, rhs: Self) -> Self;
+}
+
+impl DivRound for i32 {
+    fn div_round(self, rhs: Self) -> Self {
+        let q = self / rhs;
+        let r = self % rhs;
+
+        match 2 * r.abs() >= rhs.abs() {
+            true => q + (self.signum() * rhs.signum()),
+            false => q,
+        }
+    }
+}
+
+impl DivRound for f32 {
+    fn div_round(self, rhs: Self) -> Self {
+        (self / rhs).round()
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Unit<T>([T; 64]);
 
@@ -88,10 +142,12 @@ where T: Default + Copy + Div<Output = T> + Mul<Output = T>
         self.0
     }
 
-    pub fn quantize(self, table: Self) -> Self {
+    pub fn quantize(self, table: Self) -> Self
+    where T: DivRound
+    {
         let mut out = [T::default(); 64];
-        for i in 0..64 {
-            out[i] = self.0[i] / table.0[i];
+        for (i, x) in out.iter_mut().enumerate() {
+            *x = self.0[i].div_round(table.0[i]);
         }
 
         Self(out)
@@ -99,8 +155,8 @@ where T: Default + Copy + Div<Output = T> + Mul<Output = T>
 
     pub fn inv_quantize(self, table: Self) -> Self {
         let mut out = [T::default(); 64];
-        for i in 0..64 {
-            out[i] = self.0[i] * table.0[i];
+        for (i, x) in out.iter_mut().enumerate() {
+            *x = self.0[i] * table.0[i];
         }
 
         Self(out)
@@ -193,60 +249,102 @@ impl Unit<f32> {
 
         Unit(out_buf)
     }
+
+    /// Same as `dct`, using the factored transform of `aan`.
+    pub fn fast_dct(self) -> Unit<f32> {
+        Unit(aan::forward(&self.0))
+    }
+
+    /// Same as `inv_dct`, using the factored transform of `aan`.
+    pub fn fast_inv_dct(self) -> Unit<f32> {
+        Unit(aan::inverse(&self.0))
+    }
 }
 
 impl Unit<i32> {
+    /// Like `inv_quantize`, failing with `Error::InvalidData` on overflow or
+    /// coefficients beyond `MAX_COEFFICIENT`, which only corrupt data gives.
+    pub fn checked_inv_quantize(self, table: Self) -> Result<Self> {
+        let mut out = [0; 64];
+        for (i, x) in out.iter_mut().enumerate() {
+            *x = self.0[i]
+                .checked_mul(table.0[i])
+                .filter(|x| x.abs() <= MAX_COEFFICIENT)
+                .ok_or(Error::InvalidData)?;
+        }
+
+        Ok(Self(out))
+    }
+
+    /// Fixed-point version of `Unit::<f32>::dct`, accurate to the nearest
+    /// integer for samples of up to 12 bits.
     pub fn dct(self) -> Self {
-        let in_buf = self.0;
+        let in_buf = self.0.map(i64::from);
         let mut mid_buf = [0; 64];
         let mut out_buf = [0; 64];
 
         for k in 0..8 {
             for y in 0..8 {
-                let c: i32 = (0..8)
-                    .map(|n| in_buf[y + 8*n] * DCT_MUL_I[k + 8*n])
+                let c: i64 = (0..8)
+                    .map(|n| in_buf[y + 8*n] * DCT_MUL_I[k + 8*n] as i64)
                     .sum();
-                mid_buf[y + 8*k] = c >> DCT_MUL_SHIFT;
+                mid_buf[y + 8*k] = descale(c, DCT_MUL_SHIFT - PASS_BITS);
             }
         }
     
         for k in 0..8 {
             for x in 0..8 {
-                let c: i32 = (0..8)
-                    .map(|n| mid_buf[n + 8*x] * DCT_MUL_I[k + 8*n])
+                let c: i64 = (0..8)
+                    .map(|n| mid_buf[n + 8*x] * DCT_MUL_I[k + 8*n] as i64)
                     .sum();
-                out_buf[k + 8*x] = c >> DCT_MUL_SHIFT;
+                out_buf[k + 8*x] = descale(c, DCT_MUL_SHIFT + PASS_BITS) as i32;
             }
         }
 
         Unit(out_buf)
     }
 
+    /// Fixed-point version of `Unit::<f32>::inv_dct`, meeting the accuracy
+    /// bounds of IEEE 1180.
     pub fn inv_dct(self) -> Self {
-        let in_buf = self.0;
+        let in_buf = self.0.map(i64::from);
         let mut mid_buf = [0; 64];
         let mut out_buf = [0; 64];
 
         for k in 0..8 {
             for y in 0..8 {
-                let c: i32 = (0..8)
-                    .map(|n| in_buf[y + 8*n] * DCT_MUL_I[n + 8*k])
+                let c: i64 = (0..8)
+                    .map(|n| in_buf[y + 8*n] * DCT_MUL_I[n + 8*k] as i64)
                     .sum();
-                mid_buf[y + 8*k] = c >> DCT_MUL_SHIFT;
+                mid_buf[y + 8*k] = descale(c, DCT_MUL_SHIFT - PASS_BITS);
             }
         }
     
         for k in 0..8 {
             for x in 0..8 {
-                let c: i32 = (0..8)
-                    .map(|n| mid_buf[n + 8*x] * DCT_MUL_I[n + 8*k])
+                let c: i64 = (0..8)
+                    .map(|n| mid_buf[n + 8*x] * DCT_MUL_I[n + 8*k] as i64)
                     .sum();
-                out_buf[k + 8*x] = c >> DCT_MUL_SHIFT;
+                out_buf[k + 8*x] = descale(c, DCT_MUL_SHIFT + PASS_BITS) as i32;
             }
         }
 
         Unit(out_buf)
     }
+
+    /// Like `dct`, computed with `Unit::<f32>::fast_dct` and rounded.
+    pub fn fast_dct(self) -> Self {
+        self.convert(|x| x as f32)
+            .fast_dct()
+            .convert(|x| (x + 0.5_f32.copysign(x)) as i32)
+    }
+
+    /// Like `inv_dct`, computed with `Unit::<f32>::fast_inv_dct` and rounded.
+    pub fn fast_inv_dct(self) -> Self {
+        self.convert(|x| x as f32)
+            .fast_inv_dct()
+            .convert(|x| (x + 0.5_f32.copysign(x)) as i32)
+    }
 }
 
 impl<T> IntoIterator for Unit<T> {
@@ -283,7 +381,7 @@ mod tests {
 
         DATA
             .iter()
-            .zip(new_spacial.into_iter())
+            .zip(new_spacial)
             .for_each(|(&a, b)| assert_eq!(a, b));
     }
 
@@ -297,13 +395,178 @@ mod tests {
         DATA
             .iter()
             .zip(new_spacial.iter())
-            .for_each(|(a, b)| assert!((a - b).abs() < 8));
+            .for_each(|(a, b)| assert!((a - b).abs() <= 1));
+    }
+
+    /// Separable DCT in double precision, the reference of IEEE 1180.
+    fn reference_dct(block: [f64; 64], inverse: bool) -> [f64; 64] {
+        let basis = |k: usize, n: usize| {
+            let scale = match k {
+                0 => (0.125_f64).sqrt(),
+                _ => 0.5,
+            };
+            scale * (std::f64::consts::PI / 16.0 * (k * (2 * n + 1)) as f64).cos()
+        };
+        let weight = |k, n| match inverse {
+            true => basis(n, k),
+            false => basis(k, n),
+        };
+
+        let mut mid = [0.0; 64];
+        let mut out = [0.0; 64];
+        for k in 0..8 {
+            for y in 0..8 {
+                mid[y + 8*k] = (0..8).map(|n| block[y + 8*n] * weight(k, n)).sum();
+            }
+        }
+        for k in 0..8 {
+            for x in 0..8 {
+                out[k + 8*x] = (0..8).map(|n| mid[n + 8*x] * weight(k, n)).sum();
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn i32_inverse_dct_meets_ieee_1180() {
+        const BLOCKS: usize = 10000;
+
+        let mut state: u32 = 0x2545f491;
+        let mut random = |low: i32, high: i32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            low + (state % (high - low + 1) as u32) as i32
+        };
+
+        for (low, high) in [(-256, 255), (-5, 5), (-300, 300)] {
+            for sign in [1, -1] {
+                let mut errors = [0_i64; 64];
+                let mut squares = [0_i64; 64];
+
+                for _ in 0..BLOCKS {
+                    let spatial: [f64; 64] = std::array::from_fn(|_| (sign * random(low, high)) as f64);
+                    let coefficients = reference_dct(spatial, false).map(|x| (x.round() as i32).clamp(-2048, 2047));
+
+                    let expected = reference_dct(coefficients.map(f64::from), true)
+                        .map(|x| (x.round() as i32).clamp(-256, 255));
+                    let actual = Unit::new(coefficients).inv_dct().convert(|x| x.clamp(-256, 255));
+
+                    for (i, (a, b)) in actual.into_iter().zip(expected).enumerate() {
+                        let error = (a - b) as i64;
+                        assert!(error.abs() <= 1);
+                        errors[i] += error;
+                        squares[i] += error * error;
+                    }
+                }
+
+                let n = BLOCKS as f64;
+                assert!(errors.iter().all(|&e| (e as f64 / n).abs() <= 0.015));
+                assert!(squares.iter().all(|&e| e as f64 / n <= 0.06));
+                assert!((errors.iter().sum::<i64>() as f64 / (64.0 * n)).abs() <= 0.0015);
+                assert!(squares.iter().sum::<i64>() as f64 / (64.0 * n) <= 0.02);
+            }
+        }
+
+        assert_eq!(Unit::new([0; 64]).inv_dct(), Unit::new([0; 64]));
+    }
+
+    #[test]
+    fn i32_dct_rounds_reference() {
+        for bits in [8, 12] {
+            for block in random_blocks(bits) {
+                let expected = reference_dct(block.map(f64::from), false);
+                let actual = Unit::new(block).dct();
+
+                assert!(actual.into_iter().zip(expected).all(|(a, b)| (a as f64 - b).abs() <= 0.51));
+            }
+        }
+    }
+
+    /// Blocks of pseudo-random samples centered around zero with the given
+    /// number of bits.
+    fn random_blocks(bits: u32) -> impl Iterator<Item = [i32; 64]> {
+        let mut state: u32 = 0x9e3779b9;
+        (0..500).map(move |_| {
+            std::array::from_fn(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> (32 - bits)) as i32 - (1 << (bits - 1))
+            })
+        })
+    }
+
+    fn max_difference(a: Unit<f32>, b: Unit<f32>) -> f32 {
+        a.into_iter().zip(b).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn fast_dct_matches_reference() {
+        for (bits, tolerance) in [(8, 0.002), (12, 0.05)] {
+            for block in random_blocks(bits) {
+                let spatial = Unit::new(block).convert(|x| x as f32);
+                let coefficients = spatial.dct();
+
+                assert!(max_difference(spatial.fast_dct(), coefficients) < tolerance);
+                assert!(max_difference(coefficients.fast_inv_dct(), coefficients.inv_dct()) < tolerance);
+            }
+        }
+    }
+
+    #[test]
+    fn fast_i32_dct_matches_reference() {
+        for block in random_blocks(8) {
+            let spatial = Unit::new(block);
+            let coefficients = spatial.dct();
+
+            let differences = spatial.fast_dct().into_iter().zip(coefficients).map(|(a, b)| (a - b).abs());
+            assert!(differences.max().unwrap() <= 1);
+
+            let differences = coefficients.fast_inv_dct().into_iter().zip(coefficients.inv_dct()).map(|(a, b)| (a - b).abs());
+            assert!(differences.max().unwrap() <= 1);
+        }
+    }
+
+    #[test]
+    fn quantize_rounds_to_nearest() {
+        let mut data = [0; 64];
+        data[..8].copy_from_slice(&[7, 8, 9, -7, -8, -9, 24, -24]);
+
+        let quantized = Unit::new(data)
+            .quantize(Unit::new([16; 64]))
+            .unwrap();
+
+        assert_eq!(quantized[..8], [0, 1, 1, 0, -1, -1, 2, -2]);
+    }
+
+    #[test]
+    fn checked_inv_quantize_bounds_coefficients() {
+        // Extreme 12-bit blocks stay within bounds with the finest table
+        let checkered: [i32; 64] = std::array::from_fn(|i| match (i % 8 + i / 8) % 2 {
+            0 => 2047,
+            _ => -2048,
+        });
+        for block in [[-2048; 64], [2047; 64], checkered] {
+            let coefficients = Unit::new(block).dct();
+            assert!(coefficients.checked_inv_quantize(Unit::new([1; 64])).is_ok());
+        }
+
+        let mut data = [0; 64];
+        data[0] = 40000;
+        let result = Unit::new(data).checked_inv_quantize(Unit::new([u16::MAX as i32; 64]));
+        assert!(matches!(result, Err(Error::InvalidData)));
+
+        data[0] = MAX_COEFFICIENT / 2 + 1;
+        let result = Unit::new(data).checked_inv_quantize(Unit::new([2; 64]));
+        assert!(matches!(result, Err(Error::InvalidData)));
     }
 
     #[test]
     fn zigzag_inverse_equality() {
         let a = Unit::new(DATA);
-        let b = a.clone()
+        let b = a
             .zigzag()
             .inv_zigzag();
 
@@ -1,3 +1,23 @@
+/// Common interface of pixel types, which makes them convertible into each
+/// other through normalized RGBA.
+pub trait Pixel: Copy + Default + Send + Sync {
+    /// Type of a single channel value.
+    type Channel: Copy;
+
+    /// Number of channels stored per pixel.
+    const CHANNELS: usize;
+
+    /// Channel value a normalized component of 1 stands for, the unit in
+    /// which `metrics` reports errors.
+    const PEAK: f64;
+
+    /// Non-linear (gamma encoded) RGBA with every component from 0 to 1.
+    fn to_normalized(self) -> [f32; 4];
+
+    /// Inverse of `to_normalized`, out of range components are clamped.
+    fn from_normalized(rgba: [f32; 4]) -> Self;
+}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct Rgb8 {
     pub r: u8,
@@ -5,6 +25,47 @@ pub struct Rgb8 {
     pub b: u8,
 }
 
+/// RGB with straight (not premultiplied) alpha, 255 is opaque.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rgba8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+/// Single channel luma, weighted like the luma of `Lab8`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Gray8 {
+    pub v: u8,
+}
+
+/// RGB with 16 bits per channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rgb16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+/// Single channel luma with 16 bits, weighted like `Gray8`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Gray16 {
+    pub v: u16,
+}
+
+/// Floating point RGB, nominally from 0 to 1. Values outside of that range
+/// are kept as they are, but clamped when converting to other pixel types.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rgbf32 {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+/// Luma and chroma representation based on the BT.709 coefficients, similar to
+/// YCbCr with luma shifted to be centered around zero. Despite its name it is
+/// not CIE L\*a\*b\*, see `CieLab` for the perceptual color space.
 #[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct Lab8 {
     pub l: i8,
@@ -12,6 +73,213 @@ pub struct Lab8 {
     pub b: i8,
 }
 
+fn round_u8(x: f32) -> u8 {
+    x.round().clamp(u8::MIN as f32, u8::MAX as f32) as u8
+}
+
+fn round_i8(x: f32) -> i8 {
+    x.round().clamp(i8::MIN as f32, i8::MAX as f32) as i8
+}
+
+fn normalize_u8(x: u8) -> f32 {
+    x as f32 / u8::MAX as f32
+}
+
+fn denormalize_u8(x: f32) -> u8 {
+    round_u8(x * u8::MAX as f32)
+}
+
+fn normalize_u16(x: u16) -> f32 {
+    x as f32 / u16::MAX as f32
+}
+
+fn denormalize_u16(x: f32) -> u16 {
+    (x * u16::MAX as f32).round().clamp(u16::MIN as f32, u16::MAX as f32) as u16
+}
+
+/// BT.709 luma of non-linear RGB.
+fn luma(r: f32, g: f32, b: f32) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+impl Pixel for Rgb8 {
+    type Channel = u8;
+
+    const CHANNELS: usize = 3;
+    const PEAK: f64 = 255.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        [normalize_u8(self.r), normalize_u8(self.g), normalize_u8(self.b), 1.0]
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        Self {
+            r: denormalize_u8(rgba[0]),
+            g: denormalize_u8(rgba[1]),
+            b: denormalize_u8(rgba[2]),
+        }
+    }
+}
+
+impl Pixel for Rgba8 {
+    type Channel = u8;
+
+    const CHANNELS: usize = 4;
+    const PEAK: f64 = 255.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a].map(normalize_u8)
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        let [r, g, b, a] = rgba.map(denormalize_u8);
+        Self { r, g, b, a }
+    }
+}
+
+impl Pixel for Gray8 {
+    type Channel = u8;
+
+    const CHANNELS: usize = 1;
+    const PEAK: f64 = 255.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        let v = normalize_u8(self.v);
+        [v, v, v, 1.0]
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        Rgb8::from_normalized(rgba).into()
+    }
+}
+
+impl Pixel for Rgb16 {
+    type Channel = u16;
+
+    const CHANNELS: usize = 3;
+    const PEAK: f64 = 65535.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        [normalize_u16(self.r), normalize_u16(self.g), normalize_u16(self.b), 1.0]
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        Self {
+            r: denormalize_u16(rgba[0]),
+            g: denormalize_u16(rgba[1]),
+            b: denormalize_u16(rgba[2]),
+        }
+    }
+}
+
+impl Pixel for Gray16 {
+    type Channel = u16;
+
+    const CHANNELS: usize = 1;
+    const PEAK: f64 = 65535.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        let v = normalize_u16(self.v);
+        [v, v, v, 1.0]
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        let [r, g, b, _] = rgba.map(|x| x.clamp(0.0, 1.0));
+        Self { v: denormalize_u16(luma(r, g, b)) }
+    }
+}
+
+impl Pixel for Rgbf32 {
+    type Channel = f32;
+
+    const CHANNELS: usize = 3;
+    const PEAK: f64 = 1.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        [self.r, self.g, self.b, 1.0].map(|x| x.clamp(0.0, 1.0))
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        let [r, g, b, _] = rgba.map(|x| x.clamp(0.0, 1.0));
+        Self { r, g, b }
+    }
+}
+
+// Going through `Rgb8` keeps generic conversions consistent with `From`
+impl Pixel for Lab8 {
+    type Channel = i8;
+
+    const CHANNELS: usize = 3;
+    const PEAK: f64 = 255.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        Rgb8::from(self).to_normalized()
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        Rgb8::from_normalized(rgba).into()
+    }
+}
+
+impl From<Rgb8> for Rgba8 {
+    fn from(other: Rgb8) -> Self {
+        Self {
+            r: other.r,
+            g: other.g,
+            b: other.b,
+            a: u8::MAX,
+        }
+    }
+}
+
+impl From<Rgba8> for Rgb8 {
+    fn from(other: Rgba8) -> Self {
+        Self {
+            r: other.r,
+            g: other.g,
+            b: other.b,
+        }
+    }
+}
+
+impl From<Rgb8> for Gray8 {
+    fn from(other: Rgb8) -> Self {
+        let r = other.r as f32;
+        let g = other.g as f32;
+        let b = other.b as f32;
+
+        Self {
+            v: round_u8(luma(r, g, b)),
+        }
+    }
+}
+
+impl From<Gray8> for Rgb8 {
+    fn from(other: Gray8) -> Self {
+        Self {
+            r: other.v,
+            g: other.v,
+            b: other.v,
+        }
+    }
+}
+
+impl From<Rgb16> for Gray16 {
+    fn from(other: Rgb16) -> Self {
+        Gray16::from_normalized(other.to_normalized())
+    }
+}
+
+impl From<Gray16> for Rgb16 {
+    fn from(other: Gray16) -> Self {
+        Self {
+            r: other.v,
+            g: other.v,
+            b: other.v,
+        }
+    }
+}
+
 impl From<Rgb8> for Lab8 {
     fn from(other: Rgb8) -> Self {
         let r = other.r as f32;
@@ -19,9 +287,9 @@ impl From<Rgb8> for Lab8 {
         let b = other.b as f32;
 
         Self {
-            l: ( 0.2126 * r +  0.7152 * g +  0.0722 * b - 128.0) as i8,
-            a: (-0.1146 * r + -0.3854 * g +     0.5 * b) as i8,
-            b: (    0.5 * r + -0.4542 * g + -0.0458 * b) as i8,
+            l: round_i8( 0.2126 * r +  0.7152 * g +  0.0722 * b - 128.0),
+            a: round_i8(-0.1146 * r + -0.3854 * g +     0.5 * b),
+            b: round_i8(    0.5 * r + -0.4542 * g + -0.0458 * b),
         }
     }
 }
@@ -33,17 +301,343 @@ impl From<Lab8> for Rgb8 {
         let b = other.b as f32;
 
         Self {
-            r: (l               +  1.5748 * b) as u8,
-            g: (l + -0.1873 * a + -0.4681 * b) as u8,
-            b: (l +  1.8556 * a              ) as u8,
+            r: round_u8(l               +  1.5748 * b),
+            g: round_u8(l + -0.1873 * a + -0.4681 * b),
+            b: round_u8(l +  1.8556 * a              ),
+        }
+    }
+}
+
+/// CIE 1931 XYZ relative to the D65 white point, with Y = 1 for white.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Xyz {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+/// CIE 1976 L\*a\*b\* relative to the D65 white point, L\* ranges from 0 to 100.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CieLab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+/// Reference white of the D65 illuminant.
+const D65: Xyz = Xyz { x: 0.95047, y: 1.0, z: 1.08883 };
+
+/// Removes the sRGB transfer function from a component in the range 0 to 1.
+pub fn srgb_to_linear(x: f32) -> f32 {
+    match x <= 0.04045 {
+        true => x / 12.92,
+        false => ((x + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+/// Applies the sRGB transfer function to a linear component.
+pub fn linear_to_srgb(x: f32) -> f32 {
+    match x <= 0.0031308 {
+        true => x * 12.92,
+        false => 1.055 * x.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+impl Xyz {
+    /// Converts normalized, gamma encoded sRGB.
+    pub fn from_srgb(rgb: [f32; 3]) -> Self {
+        let [r, g, b] = rgb.map(srgb_to_linear);
+
+        Self {
+            x: 0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+            y: 0.2126729 * r + 0.7151522 * g + 0.072175 * b,
+            z: 0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+        }
+    }
+
+    /// Converts to normalized, gamma encoded sRGB. Colors outside of the sRGB
+    /// gamut give components outside of the range 0 to 1.
+    pub fn to_srgb(self) -> [f32; 3] {
+        let Xyz { x, y, z } = self;
+
+        [
+             3.2404542 * x + -1.5371385 * y + -0.4985314 * z,
+            -0.969266 * x +  1.8760108 * y +  0.041556 * z,
+             0.0556434 * x + -0.2040259 * y +  1.0572252 * z,
+        ].map(linear_to_srgb)
+    }
+}
+
+const LAB_EPSILON: f32 = 216.0 / 24389.0;
+const LAB_KAPPA: f32 = 24389.0 / 27.0;
+
+impl From<Xyz> for CieLab {
+    fn from(other: Xyz) -> Self {
+        let f = |t: f32| match t > LAB_EPSILON {
+            true => t.cbrt(),
+            false => (LAB_KAPPA * t + 16.0) / 116.0,
+        };
+
+        let fx = f(other.x / D65.x);
+        let fy = f(other.y / D65.y);
+        let fz = f(other.z / D65.z);
+
+        Self {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
         }
     }
 }
 
+impl From<CieLab> for Xyz {
+    fn from(other: CieLab) -> Self {
+        let fy = (other.l + 16.0) / 116.0;
+        let fx = fy + other.a / 500.0;
+        let fz = fy - other.b / 200.0;
+
+        let f_inv = |f: f32| match f * f * f > LAB_EPSILON {
+            true => f * f * f,
+            false => (116.0 * f - 16.0) / LAB_KAPPA,
+        };
+
+        let y = match other.l > LAB_KAPPA * LAB_EPSILON {
+            true => fy * fy * fy,
+            false => other.l / LAB_KAPPA,
+        };
+
+        Self {
+            x: f_inv(fx) * D65.x,
+            y: y * D65.y,
+            z: f_inv(fz) * D65.z,
+        }
+    }
+}
+
+impl From<Rgb8> for Xyz {
+    fn from(other: Rgb8) -> Self {
+        let [r, g, b, _] = other.to_normalized();
+        Xyz::from_srgb([r, g, b])
+    }
+}
+
+impl From<Xyz> for Rgb8 {
+    fn from(other: Xyz) -> Self {
+        let [r, g, b] = other.to_srgb();
+        Rgb8::from_normalized([r, g, b, 1.0])
+    }
+}
+
+impl From<Rgb8> for CieLab {
+    fn from(other: Rgb8) -> Self {
+        Xyz::from(other).into()
+    }
+}
+
+impl From<CieLab> for Rgb8 {
+    fn from(other: CieLab) -> Self {
+        Xyz::from(other).into()
+    }
+}
+
+impl Pixel for Xyz {
+    type Channel = f32;
+
+    const CHANNELS: usize = 3;
+    const PEAK: f64 = 1.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        let [r, g, b] = self.to_srgb().map(|x| x.clamp(0.0, 1.0));
+        [r, g, b, 1.0]
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        Xyz::from_srgb([rgba[0], rgba[1], rgba[2]].map(|x| x.clamp(0.0, 1.0)))
+    }
+}
+
+impl Pixel for CieLab {
+    type Channel = f32;
+
+    const CHANNELS: usize = 3;
+    const PEAK: f64 = 1.0;
+
+    fn to_normalized(self) -> [f32; 4] {
+        Xyz::from(self).to_normalized()
+    }
+
+    fn from_normalized(rgba: [f32; 4]) -> Self {
+        Xyz::from_normalized(rgba).into()
+    }
+}
+
+impl CieLab {
+    /// Euclidean distance, the CIE76 color difference.
+    pub fn delta_e_1976(&self, other: &CieLab) -> f32 {
+        let dl = self.l - other.l;
+        let da = self.a - other.a;
+        let db = self.b - other.b;
+
+        (dl * dl + da * da + db * db).sqrt()
+    }
+
+    /// CIEDE2000 color difference with unit weighting factors.
+    pub fn delta_e_2000(&self, other: &CieLab) -> f32 {
+        let (l1, a1, b1) = (self.l as f64, self.a as f64, self.b as f64);
+        let (l2, a2, b2) = (other.l as f64, other.a as f64, other.b as f64);
+
+        let c1 = a1.hypot(b1);
+        let c2 = a2.hypot(b2);
+        let c_mean = (c1 + c2) / 2.0;
+        let c_mean7 = c_mean.powi(7);
+        let g = 0.5 * (1.0 - (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt());
+
+        let a1 = (1.0 + g) * a1;
+        let a2 = (1.0 + g) * a2;
+        let c1 = a1.hypot(b1);
+        let c2 = a2.hypot(b2);
+
+        let hue = |b: f64, a: f64| match a == 0.0 && b == 0.0 {
+            true => 0.0,
+            false => b.atan2(a).to_degrees().rem_euclid(360.0),
+        };
+        let h1 = hue(b1, a1);
+        let h2 = hue(b2, a2);
+
+        let dl = l2 - l1;
+        let dc = c2 - c1;
+        let dh = match c1 * c2 == 0.0 {
+            true => 0.0,
+            false if (h2 - h1).abs() <= 180.0 => h2 - h1,
+            false if h2 - h1 > 180.0 => h2 - h1 - 360.0,
+            false => h2 - h1 + 360.0,
+        };
+        let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+        let l_mean = (l1 + l2) / 2.0;
+        let c_mean = (c1 + c2) / 2.0;
+        let h_mean = match c1 * c2 == 0.0 {
+            true => h1 + h2,
+            false if (h1 - h2).abs() <= 180.0 => (h1 + h2) / 2.0,
+            false if h1 + h2 < 360.0 => (h1 + h2 + 360.0) / 2.0,
+            false => (h1 + h2 - 360.0) / 2.0,
+        };
+
+        let t = 1.0
+            - 0.17 * (h_mean - 30.0).to_radians().cos()
+            + 0.24 * (2.0 * h_mean).to_radians().cos()
+            + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+            - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+
+        let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+        let c_mean7 = c_mean.powi(7);
+        let r_c = 2.0 * (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt();
+        let l_offset = (l_mean - 50.0).powi(2);
+
+        let s_l = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+        let s_c = 1.0 + 0.045 * c_mean;
+        let s_h = 1.0 + 0.015 * c_mean * t;
+        let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+        let dl = dl / s_l;
+        let dc = dc / s_c;
+        let dh = dh / s_h;
+
+        (dl * dl + dc * dc + dh * dh + r_t * dc * dh).sqrt() as f32
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn assert_lab(lab: CieLab, l: f32, a: f32, b: f32) {
+        assert!((lab.l - l).abs() < 1e-2, "{:?}", lab);
+        assert!((lab.a - a).abs() < 1e-2, "{:?}", lab);
+        assert!((lab.b - b).abs() < 1e-2, "{:?}", lab);
+    }
+
+    #[test]
+    fn rgb8_to_cie_lab() {
+        assert_lab(Rgb8 { r: 255, g: 255, b: 255 }.into(), 100.0, 0.0, 0.0);
+        assert_lab(Rgb8 { r: 0, g: 0, b: 0 }.into(), 0.0, 0.0, 0.0);
+        assert_lab(Rgb8 { r: 255, g: 0, b: 0 }.into(), 53.2408, 80.0925, 67.2032);
+        assert_lab(Rgb8 { r: 0, g: 0, b: 255 }.into(), 32.2970, 79.1875, -107.8602);
+        assert_lab(Rgb8 { r: 128, g: 128, b: 128 }.into(), 53.5850, 0.0, 0.0);
+    }
+
+    #[test]
+    fn cie_lab_round_trip() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(17) {
+                let p = Rgb8 { r, g, b: 255 - r / 2 };
+                assert_eq!(Rgb8::from(CieLab::from(p)), p);
+            }
+        }
+    }
+
+    #[test]
+    fn rgba8_conversions() {
+        let p = Rgba8 { r: 10, g: 20, b: 30, a: 40 };
+        assert_eq!(Rgba8::from_normalized(p.to_normalized()), p);
+        assert_eq!(Rgb8::from(p), Rgb8 { r: 10, g: 20, b: 30 });
+        assert_eq!(Rgba8::from(Rgb8::from(p)), Rgba8 { a: 255, ..p });
+        assert_eq!(Rgb8::from_normalized(p.to_normalized()), Rgb8::from(p));
+    }
+
+    #[test]
+    fn high_depth_conversions() {
+        let p = Rgb16 { r: 1000, g: 40000, b: 65535 };
+        assert_eq!(Rgb16::from_normalized(p.to_normalized()), p);
+        assert_eq!(Rgb8::from_normalized(p.to_normalized()), Rgb8 { r: 4, g: 156, b: 255 });
+
+        let gray = Gray16 { v: 12345 };
+        assert_eq!(Gray16::from(Rgb16::from(gray)), gray);
+        assert_eq!(Gray16::from_normalized(gray.to_normalized()), gray);
+
+        let f = Rgbf32 { r: 0.25, g: 1.5, b: -0.5 };
+        assert_eq!(f.to_normalized(), [0.25, 1.0, 0.0, 1.0]);
+        assert_eq!(Rgbf32::from_normalized(Rgb8 { r: 51, g: 0, b: 255 }.to_normalized()),
+            Rgbf32 { r: 0.2, g: 0.0, b: 1.0 });
+    }
+
+    #[test]
+    fn gray8_conversions() {
+        for v in 0..=255 {
+            let gray = Gray8 { v };
+            assert_eq!(Gray8::from(Rgb8::from(gray)), gray);
+            assert_eq!(Gray8::from_normalized(gray.to_normalized()), gray);
+        }
+
+        assert_eq!(Gray8::from(Rgb8 { r: 255, g: 0, b: 0 }), Gray8 { v: 54 });
+        assert_eq!(Gray8::from(Rgb8 { r: 0, g: 255, b: 0 }), Gray8 { v: 182 });
+    }
+
+    #[test]
+    fn delta_e() {
+        let a = CieLab { l: 50.0, a: 0.0, b: 0.0 };
+        let b = CieLab { l: 53.0, a: 4.0, b: 0.0 };
+        assert_eq!(a.delta_e_1976(&b), 5.0);
+
+        // Reference pairs from Sharma, Wu and Dalal (2005)
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+            ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+            ((50.0, 2.5, 0.0), (50.0, 0.0, -2.5), 4.3065),
+            ((60.2574, -34.0099, 36.2677), (60.4626, -34.1751, 39.4387), 1.2644),
+            ((2.0776, 0.0795, -1.1350), (0.9033, -0.0636, -0.5514), 0.9082),
+        ];
+
+        for ((l1, a1, b1), (l2, a2, b2), expected) in pairs {
+            let x = CieLab { l: l1, a: a1, b: b1 };
+            let y = CieLab { l: l2, a: a2, b: b2 };
+
+            assert!((x.delta_e_2000(&y) - expected).abs() < 1e-3);
+            assert!((y.delta_e_2000(&x) - expected).abs() < 1e-3);
+        }
+    }
+
     #[test]
     fn lab8_to_rgb8() {
         let black: Rgb8 = Lab8 { l: -128, a: 0, b: 0 } .into();
@@ -53,6 +647,38 @@ mod tests {
         assert_eq!(white, Rgb8 { r: 255, g: 255, b: 255 });
     }
 
+    #[test]
+    fn rgb8_round_trip_is_unbiased() {
+        let mut error = 0;
+        let mut max_error = 0;
+
+        for v in (0..=255).step_by(3) {
+            for w in (0..=255).step_by(5) {
+                let p = Rgb8 { r: v, g: w, b: 255 - v };
+                let q: Rgb8 = Lab8::from(p).into();
+
+                for (x, y) in [(p.r, q.r), (p.g, q.g), (p.b, q.b)] {
+                    error += x as i32 - y as i32;
+                    max_error = max_error.max((x as i32 - y as i32).abs());
+                }
+            }
+        }
+
+        assert!(max_error <= 2);
+        assert!(error.abs() < 86 * 52 * 3 / 10);
+    }
+
+    #[test]
+    fn normalized_round_trip() {
+        for v in 0..=255 {
+            let p = Rgb8 { r: v, g: 255 - v, b: v / 2 };
+            assert_eq!(Rgb8::from_normalized(p.to_normalized()), p);
+        }
+
+        let lab = Lab8 { l: 20, a: -5, b: 7 };
+        assert_eq!(Lab8::from_normalized(lab.to_normalized()), Lab8::from(Rgb8::from(lab)));
+    }
+
     #[test]
     fn rgb8_to_lab8() {
         let black: Lab8 = Rgb8 { r: 0, g: 0, b: 0 } .into();
@@ -0,0 +1,61 @@
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidMagic,
+    InvalidHeader,
+    InvalidDimensions { width: usize, height: usize },
+    UnsupportedMaxval(u32),
+    Truncated,
+    InvalidData,
+    UnsupportedVersion(u8),
+    UnsupportedFeature(&'static str),
+    InvalidQuality(usize),
+    InvalidTable,
+    DimensionMismatch,
+    TargetUnreachable,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "i/o error: {}", err),
+            Error::InvalidMagic => write!(f, "invalid magic number"),
+            Error::InvalidHeader => write!(f, "malformed header"),
+            Error::InvalidDimensions { width, height } => {
+                write!(f, "invalid image dimensions {}x{}", width, height)
+            }
+            Error::UnsupportedMaxval(maxval) => write!(f, "unsupported maxval {}", maxval),
+            Error::Truncated => write!(f, "unexpected end of image data"),
+            Error::InvalidData => write!(f, "corrupt image data"),
+            Error::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+            Error::UnsupportedFeature(feature) => write!(f, "unsupported {}", feature),
+            Error::InvalidQuality(quality) => write!(f, "quality {} out of range", quality),
+            Error::InvalidTable => write!(f, "quantization table entry out of range"),
+            Error::DimensionMismatch => write!(f, "images have different dimensions"),
+            Error::TargetUnreachable => write!(f, "no quality setting meets the target"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Truncated,
+            _ => Error::Io(err),
+        }
+    }
+}
@@ -1,4 +1,9 @@
-use crate::color::{Rgb8, Lab8};
+use crate::color::{Pixel, Rgb8, Lab8};
+use crate::parallel;
+
+/// Number of pixels processed together by `Image::map`, large enough to
+/// amortize per-chunk overhead when chunks are handed out to workers.
+const MAP_CHUNK: usize = 4096;
 
 #[derive(Debug, Clone)]
 pub struct Image<T> {
@@ -36,13 +41,92 @@ impl<T> Image<T> {
         self.height
     }
 
-    pub fn iter_block(&self) -> ImageBlockIterator<T> {
+    /// Iterates over rows of pixels.
+    pub fn rows(&self) -> std::slice::Chunks<'_, T> {
+        self.data.chunks(self.width.max(1))
+    }
+
+    /// Applies `f` to every pixel, producing a new image of the same size.
+    /// Runs on multiple threads with the `parallel` feature.
+    pub fn map<U, F>(&self, f: F) -> Image<U>
+    where
+        T: Sync,
+        U: Send,
+        F: Fn(&T) -> U + Sync + Send,
+    {
+        Image {
+            data: parallel::map_chunked(&self.data, MAP_CHUNK, f),
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Converts between any two pixel types through normalized RGBA.
+    pub fn convert<U>(&self) -> Image<U>
+    where
+        T: Pixel,
+        U: Pixel,
+    {
+        self.map(|&p| U::from_normalized(p.to_normalized()))
+    }
+
+    /// Copies the top left `width`x`height` region of the image.
+    pub fn crop(&self, width: usize, height: usize) -> Image<T>
+    where T: Copy
+    {
+        let width = width.min(self.width);
+        let height = height.min(self.height);
+
+        Self {
+            data: self.data
+                .chunks(self.width.max(1))
+                .take(height)
+                .flat_map(|row| &row[..width])
+                .copied()
+                .collect(),
+            width,
+            height,
+        }
+    }
+
+    /// Copies the 8x8 block at block coordinates `(x, y)`, replicating the
+    /// last column and row for pixels outside of the image. Empty images
+    /// give a block of default values.
+    pub fn block(&self, x: usize, y: usize) -> [T; 64]
+    where T: Default + Copy
+    {
+        let mut u = [T::default(); 64];
+        if self.width == 0 || self.height == 0 {
+            return u;
+        }
+
+        for i in 0..8 {
+            for j in 0..8 {
+                let px = (i + 8 * x).min(self.width - 1);
+                let py = (j + 8 * y).min(self.height - 1);
+                u[i + 8 * j] = *self.at(px, py);
+            }
+        }
+
+        u
+    }
+
+    /// Iterates over 8x8 blocks in row-major order. Partial blocks at the
+    /// right and bottom edges are padded by replicating the last column and
+    /// row of the image.
+    pub fn iter_block(&self) -> ImageBlockIterator<'_, T> {
+        let w = self.width.div_ceil(8);
+        let h = match w {
+            0 => 0,
+            _ => self.height.div_ceil(8),
+        };
+
         ImageBlockIterator {
             image: self,
             x: 0,
             y: 0,
-            w: self.width / 8,
-            h: self.height / 8,
+            w,
+            h,
         }
     }
 }
@@ -61,7 +145,6 @@ where T: Default + Copy
     type Item = [T; 64];
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut u = [T::default(); 64];
         let x = self.x;
         let y = self.y;
 
@@ -69,13 +152,7 @@ where T: Default + Copy
             None
         }
         else {
-            for i in 0..8 {
-                for j in 0..8 {
-                    let index = i + (8 * x) + (8 * y + j) * self.image.width();
-                    let color = self.image.data()[index];
-                    u[i + 8 * j] = color;
-                }
-            }
+            let u = self.image.block(x, y);
     
             self.x += 1;
             if self.x == self.w {
@@ -88,51 +165,79 @@ where T: Default + Copy
     }
 }
 
-impl<T> Image<T> {
-
-}
-
-/*
-impl<F, T> From<&Image<F>> for Image<T> 
+impl<F, T> From<&Image<F>> for Image<T>
 where
-    T: From<F>,
-    F: Copy
+    F: Pixel,
+    T: Pixel,
 {
     fn from(other: &Image<F>) -> Self {
-        Self {
-            data: other.data
-                .iter()
-                .map(|&x| T::from(x))
-                .collect(),
-            width: other.width,
-            height: other.height,
-        }
+        other.convert()
     }
 }
-*/
 
 impl From<Image<Rgb8>> for Image<Lab8> {
     fn from(other: Image<Rgb8>) -> Self {
-        Self {
-            data: other.data
-                .iter()
-                .map(|&x| Lab8::from(x))
-                .collect(),
-            width: other.width,
-            height: other.height,
-        }
+        other.map(|&x| Lab8::from(x))
     }
 }
 
 impl From<Image<Lab8>> for Image<Rgb8> {
     fn from(other: Image<Lab8>) -> Self {
-        Self {
-            data: other.data
-                .iter()
-                .map(|&x| Rgb8::from(x))
-                .collect(),
-            width: other.width,
-            height: other.height,
-        }
+        other.map(|&x| Rgb8::from(x))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter_block_replicates_edges() {
+        let image = Image::new(9, 2, (0..18).collect::<Vec<i32>>());
+        let blocks: Vec<[i32; 64]> = image.iter_block().collect();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0][0..8], [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(blocks[0][8..16], [9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(blocks[0][56..64], [9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(blocks[1][0..8], [8; 8]);
+        assert_eq!(blocks[1][56..64], [17; 8]);
+    }
+
+    #[test]
+    fn empty_images_crop_and_block() {
+        let image: Image<i32> = Image::new(0, 3, Vec::new());
+
+        let cropped = image.crop(4, 2);
+        assert_eq!((cropped.width(), cropped.height()), (0, 2));
+        assert!(cropped.data().is_empty());
+        assert_eq!(image.block(0, 0), [0; 64]);
+    }
+
+    #[test]
+    fn generic_conversion_matches_from() {
+        let data = (0..300)
+            .map(|i| Rgb8 { r: i as u8, g: (i * 7) as u8, b: (i * 13) as u8 })
+            .collect();
+        let image = Image::new(20, 15, data);
+
+        let lab: Image<Lab8> = image.convert();
+        let by_value: Image<Lab8> = image.clone().into();
+        assert_eq!(lab.data(), by_value.data());
+
+        let rgb = Image::<Rgb8>::from(&lab);
+        let by_value: Image<Rgb8> = lab.into();
+        assert_eq!(rgb.data(), by_value.data());
+    }
+
+    #[test]
+    fn map_preserves_dimensions() {
+        let image = Image::new(3, 2, vec![1, 2, 3, 4, 5, 6]);
+        let doubled = image.map(|&x| x * 2);
+
+        assert_eq!(doubled.width(), 3);
+        assert_eq!(doubled.height(), 2);
+        assert_eq!(doubled.data(), &vec![2, 4, 6, 8, 10, 12]);
+        assert_eq!(doubled.rows().nth(1).unwrap(), &[8, 10, 12]);
+    }
+}
@@ -1,13 +1,21 @@
-#![forbid(unsafe_code)]
+// Only the SIMD intrinsics of `codec::rcr::aan::simd` opt out of this
+#![deny(unsafe_code)]
 // #![deny(missing_docs)]
 
 // #[macro_use]
 // extern crate lazy_static;
 
+mod error;
+mod parallel;
+
+pub use error::{Error, Result};
+
 pub mod codec {
+    pub mod jpeg;
     pub mod ppm;
     pub mod rcr;
 }
 
 pub mod color;
 pub mod image;
+pub mod metrics;
@@ -0,0 +1,355 @@
+use std::ops::Range;
+
+use crate::color::Pixel;
+use crate::error::{Error, Result};
+use crate::image::Image;
+
+/// Result of a measurement for each channel and for the whole image.
+/// Channels are those of normalized RGBA: luma alone for single channel
+/// pixels, alpha only for pixels that store it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measure {
+    pub channels: Vec<f64>,
+    pub combined: f64,
+}
+
+impl Measure {
+    fn new(channels: Vec<f64>) -> Self {
+        let combined = channels.iter().sum::<f64>() / channels.len() as f64;
+        Self { channels, combined }
+    }
+}
+
+/// Components of `Pixel::to_normalized` compared for pixels of type `P`.
+fn components<P>() -> Range<usize>
+where P: Pixel
+{
+    match P::CHANNELS {
+        1 => 0..1,
+        4 => 0..4,
+        _ => 0..3,
+    }
+}
+
+/// Components of a pixel scaled from 0 to `P::PEAK`.
+fn samples<P>(p: P) -> impl Iterator<Item = f64>
+where P: Pixel
+{
+    let rgba = p.to_normalized();
+    components::<P>().map(move |c| (rgba[c] * P::PEAK as f32) as f64)
+}
+
+fn check_dimensions<T>(a: &Image<T>, b: &Image<T>) -> Result<()> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(Error::DimensionMismatch);
+    }
+
+    if a.width() == 0 || a.height() == 0 {
+        return Err(Error::InvalidDimensions {
+            width: a.width(),
+            height: a.height(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Splits an image into one plane of values per channel.
+fn planes<P>(image: &Image<P>) -> Vec<Vec<f64>>
+where P: Pixel
+{
+    let mut planes = vec![Vec::with_capacity(image.data().len()); components::<P>().len()];
+    for &p in image.data() {
+        for (plane, x) in planes.iter_mut().zip(samples(p)) {
+            plane.push(x);
+        }
+    }
+
+    planes
+}
+
+/// Mean squared error in units of `Pixel::PEAK`, the combined value averages
+/// all channels.
+pub fn mse<P>(a: &Image<P>, b: &Image<P>) -> Result<Measure>
+where P: Pixel
+{
+    check_dimensions(a, b)?;
+
+    let mut sums = vec![0.0; components::<P>().len()];
+    for (&p, &q) in a.data().iter().zip(b.data()) {
+        for (sum, (x, y)) in sums.iter_mut().zip(samples(p).zip(samples(q))) {
+            *sum += (x - y) * (x - y);
+        }
+    }
+
+    Ok(Measure::new(sums.iter().map(|sum| sum / a.data().len() as f64).collect()))
+}
+
+/// Peak signal to noise ratio in decibels, infinite for identical images.
+pub fn psnr<P>(a: &Image<P>, b: &Image<P>) -> Result<Measure>
+where P: Pixel
+{
+    let mse = mse(a, b)?;
+    let helper = |mse: f64| 10.0 * (P::PEAK * P::PEAK / mse).log10();
+
+    Ok(Measure {
+        channels: mse.channels.iter().map(|&mse| helper(mse)).collect(),
+        combined: helper(mse.combined),
+    })
+}
+
+const WINDOW_RADIUS: usize = 5;
+const WINDOW_SIGMA: f64 = 1.5;
+
+/// Normalized 11-tap gaussian used as the SSIM window.
+fn window() -> [f64; 2 * WINDOW_RADIUS + 1] {
+    let mut w = [0.0; 2 * WINDOW_RADIUS + 1];
+    for (i, x) in w.iter_mut().enumerate() {
+        let d = i as f64 - WINDOW_RADIUS as f64;
+        *x = (-d * d / (2.0 * WINDOW_SIGMA * WINDOW_SIGMA)).exp();
+    }
+
+    let sum: f64 = w.iter().sum();
+    w.map(|x| x / sum)
+}
+
+/// Separable gaussian blur, clamping reads at the edges.
+fn blur(plane: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let w = window();
+    let r = WINDOW_RADIUS as isize;
+    let clamp = |x: isize, n: usize| x.clamp(0, n as isize - 1) as usize;
+
+    let mut mid = vec![0.0; plane.len()];
+    for y in 0..height {
+        for x in 0..width {
+            mid[x + y * width] = (-r..=r)
+                .map(|k| w[(k + r) as usize] * plane[clamp(x as isize + k, width) + y * width])
+                .sum();
+        }
+    }
+
+    let mut out = vec![0.0; plane.len()];
+    for y in 0..height {
+        for x in 0..width {
+            out[x + y * width] = (-r..=r)
+                .map(|k| w[(k + r) as usize] * mid[x + clamp(y as isize + k, height) * width])
+                .sum();
+        }
+    }
+
+    out
+}
+
+/// Mean luminance and contrast-structure terms of SSIM for a single plane.
+fn ssim_terms(a: &[f64], b: &[f64], width: usize, height: usize, peak: f64) -> (f64, f64) {
+    let c1 = (0.01 * peak) * (0.01 * peak);
+    let c2 = (0.03 * peak) * (0.03 * peak);
+
+    let product = |f: fn(f64, f64) -> f64| -> Vec<f64> {
+        a.iter().zip(b).map(|(&x, &y)| f(x, y)).collect()
+    };
+
+    let mu_a = blur(a, width, height);
+    let mu_b = blur(b, width, height);
+    let aa = blur(&product(|x, _| x * x), width, height);
+    let bb = blur(&product(|_, y| y * y), width, height);
+    let ab = blur(&product(|x, y| x * y), width, height);
+
+    let mut luminance = 0.0;
+    let mut contrast_structure = 0.0;
+
+    for i in 0..a.len() {
+        let var_a = aa[i] - mu_a[i] * mu_a[i];
+        let var_b = bb[i] - mu_b[i] * mu_b[i];
+        let cov = ab[i] - mu_a[i] * mu_b[i];
+
+        luminance += (2.0 * mu_a[i] * mu_b[i] + c1) / (mu_a[i] * mu_a[i] + mu_b[i] * mu_b[i] + c1);
+        contrast_structure += (2.0 * cov + c2) / (var_a + var_b + c2);
+    }
+
+    let n = a.len() as f64;
+    (luminance / n, contrast_structure / n)
+}
+
+fn measure_planes<P, F>(a: &Image<P>, b: &Image<P>, f: F) -> Result<Measure>
+where
+    P: Pixel,
+    F: Fn(&[f64], &[f64]) -> f64,
+{
+    check_dimensions(a, b)?;
+
+    let pa = planes(a);
+    let pb = planes(b);
+
+    Ok(Measure::new(pa.iter().zip(&pb).map(|(x, y)| f(x, y)).collect()))
+}
+
+/// Structural similarity index with an 11x11 gaussian window, 1 for identical
+/// images. The combined value averages all channels.
+pub fn ssim<P>(a: &Image<P>, b: &Image<P>) -> Result<Measure>
+where P: Pixel
+{
+    let (width, height) = (a.width(), a.height());
+
+    measure_planes(a, b, |x, y| {
+        let (l, cs) = ssim_terms(x, y, width, height, P::PEAK);
+        l * cs
+    })
+}
+
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// Halves both dimensions of a plane by averaging 2x2 boxes.
+fn halve(plane: &[f64], width: usize, height: usize) -> (Vec<f64>, usize, usize) {
+    let (w, h) = (width / 2, height / 2);
+    let mut out = Vec::with_capacity(w * h);
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = 2 * x + 2 * y * width;
+            out.push((plane[i] + plane[i + 1] + plane[i + width] + plane[i + width + 1]) / 4.0);
+        }
+    }
+
+    (out, w, h)
+}
+
+/// Multi-scale SSIM over up to five dyadic scales. Images too small for all
+/// scales use as many as fit the window, with the weights renormalized.
+pub fn ms_ssim<P>(a: &Image<P>, b: &Image<P>) -> Result<Measure>
+where P: Pixel
+{
+    measure_planes(a, b, |x, y| {
+        let mut x = x.to_vec();
+        let mut y = y.to_vec();
+        let (mut width, mut height) = (a.width(), a.height());
+
+        let mut scales = Vec::new();
+        for scale in 0..MS_SSIM_WEIGHTS.len() {
+            if scale > 0 && width.min(height) < 2 * (2 * WINDOW_RADIUS + 1) {
+                break;
+            }
+            if scale > 0 {
+                let (hx, w, h) = halve(&x, width, height);
+                let (hy, _, _) = halve(&y, width, height);
+                (x, y, width, height) = (hx, hy, w, h);
+            }
+
+            scales.push(ssim_terms(&x, &y, width, height, P::PEAK));
+        }
+
+        let weights = &MS_SSIM_WEIGHTS[..scales.len()];
+        let total: f64 = weights.iter().sum();
+        let last = scales.len() - 1;
+
+        scales
+            .iter()
+            .zip(weights)
+            .enumerate()
+            .map(|(i, (&(l, cs), w))| {
+                let term = if i == last { l * cs } else { cs };
+                term.max(0.0).powf(w / total)
+            })
+            .product()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Gray8, Lab8, Rgb16, Rgb8, Rgba8};
+
+    fn gradient(width: usize, height: usize, noise: i32) -> Image<Rgb8> {
+        let data = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as i32, (i / width) as i32);
+                let n = match (x * 7 + y * 13) % 3 {
+                    0 => -noise,
+                    1 => noise,
+                    _ => 0,
+                };
+                let v = |c: i32| (c + n).clamp(0, 255) as u8;
+
+                Rgb8 { r: v(x * 2), g: v(y * 2), b: v(x + y) }
+            })
+            .collect();
+
+        Image::new(width, height, data)
+    }
+
+    #[test]
+    fn identical_images() {
+        let a = gradient(40, 30, 0);
+
+        assert_eq!(mse(&a, &a).unwrap().combined, 0.0);
+        assert_eq!(psnr(&a, &a).unwrap().combined, f64::INFINITY);
+        assert!((ssim(&a, &a).unwrap().combined - 1.0).abs() < 1e-9);
+        assert!((ms_ssim(&a, &a).unwrap().combined - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mse_and_psnr_values() {
+        let a = Image::new(2, 1, vec![Rgb8 { r: 0, g: 0, b: 0 }; 2]);
+        let b = Image::new(2, 1, vec![
+            Rgb8 { r: 10, g: 0, b: 0 },
+            Rgb8 { r: 0, g: 0, b: 5 },
+        ]);
+
+        let mse = mse(&a, &b).unwrap();
+        assert_eq!(mse.channels, [50.0, 0.0, 12.5]);
+        assert_eq!(mse.combined, 62.5 / 3.0);
+
+        let psnr = psnr(&a, &b).unwrap();
+        assert!((psnr.channels[0] - 31.1411).abs() < 1e-3);
+        assert_eq!(psnr.channels[1], f64::INFINITY);
+    }
+
+    #[test]
+    fn similarity_drops_with_noise() {
+        let a = gradient(96, 96, 0);
+        let slight = gradient(96, 96, 4);
+        let strong = gradient(96, 96, 32);
+
+        assert!(ssim(&a, &slight).unwrap().combined > ssim(&a, &strong).unwrap().combined);
+        assert!(ms_ssim(&a, &slight).unwrap().combined > ms_ssim(&a, &strong).unwrap().combined);
+        assert!(psnr(&a, &slight).unwrap().combined > psnr(&a, &strong).unwrap().combined);
+    }
+
+    #[test]
+    fn dimension_mismatch_is_rejected() {
+        let a = gradient(8, 8, 0);
+        let b = gradient(8, 9, 0);
+
+        assert!(matches!(mse(&a, &b), Err(Error::DimensionMismatch)));
+        assert!(matches!(ssim(&a, &b), Err(Error::DimensionMismatch)));
+    }
+
+    #[test]
+    fn lab8_images() {
+        let a: Image<Lab8> = gradient(24, 24, 0).into();
+        let b: Image<Lab8> = gradient(24, 24, 8).into();
+
+        assert!(psnr(&a, &b).unwrap().combined > 20.0);
+        assert!(ssim(&a, &b).unwrap().combined < 1.0);
+    }
+
+    #[test]
+    fn channels_follow_the_pixel_type() {
+        let a = gradient(24, 24, 0);
+        let b = gradient(24, 24, 8);
+        let reference = psnr(&a, &b).unwrap();
+
+        // Errors scale with the peak, so PSNR does not depend on the depth
+        let wide = psnr::<Rgb16>(&a.convert(), &b.convert()).unwrap();
+        assert_eq!(wide.channels.len(), 3);
+        assert!((wide.combined - reference.combined).abs() < 1e-3);
+
+        let gray = mse::<Gray8>(&a.convert(), &b.convert()).unwrap();
+        assert_eq!(gray.channels.len(), 1);
+        assert_eq!(gray.combined, gray.channels[0]);
+
+        let alpha = mse::<Rgba8>(&a.convert(), &b.convert()).unwrap();
+        assert_eq!(alpha.channels.len(), 4);
+        assert_eq!(alpha.channels[3], 0.0);
+    }
+}
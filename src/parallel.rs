@@ -0,0 +1,50 @@
+//! Data parallel helpers, running on multiple threads with the `parallel`
+//! feature and serially otherwise. Results keep the order of the input, so
+//! they do not depend on the feature.
+
+use std::ops::Range;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Applies `f` to every item.
+pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    return items.par_iter().map(f).collect();
+
+    #[cfg(not(feature = "parallel"))]
+    return items.iter().map(f).collect();
+}
+
+/// Applies `f` to every index in `range`.
+pub fn map_range<U, F>(range: Range<usize>, f: F) -> Vec<U>
+where
+    U: Send,
+    F: Fn(usize) -> U + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    return range.into_par_iter().map(f).collect();
+
+    #[cfg(not(feature = "parallel"))]
+    return range.map(f).collect();
+}
+
+/// Applies `f` to every item in chunks of `size` items, which each thread
+/// works through as a whole.
+pub fn map_chunked<T, U, F>(items: &[T], size: usize, f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    return items.par_chunks(size).flat_map_iter(|chunk| chunk.iter().map(&f)).collect();
+
+    #[cfg(not(feature = "parallel"))]
+    return items.chunks(size).flat_map(|chunk| chunk.iter().map(&f)).collect();
+}